@@ -1,20 +1,27 @@
 //! GozScoring Abscissa Application
 
 use crate::prelude::*;
-use crate::{commands::GozScoringCmd, config::GozScoringConfig};
+use crate::{
+    checkpoint::Checkpoint,
+    commands::GozScoringCmd,
+    config::GozScoringConfig,
+    error::{Error, ErrorKind},
+    history::History,
+    input::Position,
+    ledger::Ledger,
+    metrics::{self, Activity},
+    phases::{self, ScoringPhase, Standing},
+    quarantine::{Quarantine, Rejection},
+    report::{Format, InputReport, Report},
+};
 use abscissa_core::{
     application::{self, AppCell},
-    config, trace, Application, EntryPoint, FrameworkError, StandardPaths,
+    config, trace, Application, EntryPoint, FrameworkError, FrameworkErrorKind, StandardPaths,
 };
-use relayer_modules::events::IBCEvent;
 use sagan::message::Envelope;
-use serde::{Deserialize, Serialize};
-use std::collections::BTreeSet;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::fmt;
-use std::fs::OpenOptions;
-use std::io::Write;
-use subtle_encoding::bech32::{decode, encode};
+use std::path::{Path, PathBuf};
 
 /// Application state
 pub static APPLICATION: AppCell<GozScoringApp> = AppCell::new();
@@ -38,203 +45,259 @@ pub fn app_config() -> config::Reader<GozScoringApp> {
     config::Reader::new(&APPLICATION)
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-pub struct Score {
-    pub hub_opaque_packets: u64,
-    pub opaque_packets_tx: u64,
-    pub packets_from_hub: u64,
-    pub opaque_packets_total: u64,
-}
-
-impl Default for Score {
-    fn default() -> Self {
-        Self {
-            hub_opaque_packets: 0,
-            opaque_packets_tx: 0,
-            packets_from_hub: 0,
-            opaque_packets_total: 0,
-        }
-    }
-}
 /// GozScoring Application
 #[derive(Debug)]
 pub struct GozScoringApp {
     /// Application configuration.
     config: Option<GozScoringConfig>,
 
-    /// Score for each team
-    scores: HashMap<String, Score>,
+    /// Phases being scored
+    phases: Vec<Box<dyn ScoringPhase>>,
 
-    /// Application state.
-    state: application::State<GozScoringApp>,
+    /// Audit ledger of every scoring decision
+    ledger: Ledger,
 
-    /// Hashmap from Address to team
-    address_to_team: HashMap<String, String>,
+    /// Quarantine of unparseable input lines
+    quarantine: Quarantine,
 
-    ///Source channels on the Hub
-    source_channels: BTreeSet<String>,
+    /// Number of input lines rejected
+    rejected: u64,
 
-    ///Source channels on the Hub
-    observed_transactions: BTreeSet<String>,
+    /// Inputs scored and how far, in the order they were first scored
+    inputs: Vec<(PathBuf, Position)>,
+
+    /// Envelopes scored and parse failures, for the metrics
+    activity: Activity,
+
+    /// Application state.
+    state: application::State<GozScoringApp>,
 }
 
 impl fmt::Display for GozScoringApp {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for (team, score) in self.scores.iter() {
-            let total_score = (score.hub_opaque_packets as f64)
-                + (score.packets_from_hub as f64 * 0.5)
-                + (score.opaque_packets_tx as f64 * 0.1);
-
-            write!(
-                f,
-                "Team:{}, Total Phase 2 Score {}, Total Packets Relayed{})\n",
-                team, total_score, score.opaque_packets_total
-            )?;
-        }
-
-        Ok(())
+        f.write_str(&self.report().render(Format::Text))
     }
 }
 
 impl GozScoringApp {
-    pub fn print(&self) {
-        let mut buf = Vec::new();
-        for (team, score) in self.scores.iter() {
-            let total_score = (score.hub_opaque_packets as f64)
-                + (score.packets_from_hub as f64 * 0.5)
-                + (score.opaque_packets_tx as f64 * 0.1);
-
-            write!(
-                &mut buf,
-                "Team:{}, Total Phase 2 Score {}, Total Packets Relayed {})\n",
-                team, total_score, score.opaque_packets_total
-            )
-            .unwrap();
+    /// Results of every phase and what was scored, with the teams ranked
+    /// using the configured tie-breaker
+    pub fn report(&self) -> Report {
+        let tie_breaker = self
+            .config
+            .as_ref()
+            .map(|config| config.tie_breaker)
+            .unwrap_or_default();
+
+        Report {
+            phases: self
+                .phases
+                .iter()
+                .map(|phase| {
+                    let mut report = phase.report();
+                    report.rank(tie_breaker);
+                    report
+                })
+                .collect(),
+            inputs: self
+                .inputs
+                .iter()
+                .map(|(path, position)| InputReport {
+                    path: path.clone(),
+                    lines: position.line,
+                })
+                .collect(),
+            rejected: self.rejected,
         }
+    }
+
+    /// Cumulative score of every team over time in each phase, bucketed as
+    /// configured and ranked like the results
+    pub fn history(&self) -> History {
+        let (buckets, tie_breaker) = self
+            .config
+            .as_ref()
+            .map(|config| (config.history, config.tie_breaker))
+            .unwrap_or_default();
+
+        History {
+            by: buckets.by,
+            size: buckets.size,
+            phases: self
+                .phases
+                .iter()
+                .map(|phase| {
+                    let mut history = phase.history(buckets);
+
+                    for bucket in history.buckets.iter_mut() {
+                        bucket.rank(tie_breaker);
+                    }
 
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create_new(true)
-            .open("results.txt")
-            .unwrap();
-        file.write_all(&buf).unwrap();
+                    history
+                })
+                .collect(),
+        }
     }
 
     pub fn score_envelope(&mut self, envelope: Envelope) {
-        for message in envelope.msg {
-            // status_ok!("Running", "Processing Message {:?}", message );
-
-            match message {
-                sagan::message::Message::EventIBC(ref event) => {
-                    match event {
-                        /// Compute all the scoring for an opaque packet
-                        IBCEvent::OpaquePacket(ref inner_event) => {
-                            status_ok!("Processing oqaque packet", " got event");
-                            if let Some(tx_hashes) = inner_event.data.get("tx.hash") {
-                                if let Some(hash) = tx_hashes.get(0) {
-                                    /// Don't double count packets
-                                    if !self.observed_transactions.contains(hash) {
-                                        status_ok!("Processing oqaque packet", " Hash Unknown");
-
-                                        self.observed_transactions.insert(hash.clone());
-
-                                        if let Some(senders) =
-                                            inner_event.data.get("message.sender")
-                                        {
-                                            status_ok!("Processing oqaque packet", "Got Senders");
-
-                                            if let Some(src_channels) = inner_event
-                                                .data
-                                                .get("recv_packet.packet_src_channel")
-                                            {
-                                                // Get the second to last sender to use to assign a packet to a team
-                                                if let Some(sender_address) =
-                                                    senders.get(src_channels.len() + 1)
-                                                {
-                                                    status_ok!(
-                                                        "Processing oqaque packet",
-                                                        "Got Sender"
-                                                    );
-
-                                                    if let Some(team) =
-                                                        self.get_team_by_address(sender_address)
-                                                    {
-                                                        status_ok!(
-                                                            "Processing oqaque packet",
-                                                            "Scoring"
-                                                        );
-
-                                                        let score = self
-                                                            .scores
-                                                            .entry(team.clone())
-                                                            .or_insert(Score::default());
-
-                                                        if let Some(config) = &self.config {
-                                                            if let Some(channel) =
-                                                                src_channels.get(0)
-                                                            {
-                                                                if config.hub_id.contains(
-                                                                    &envelope.network.to_string(),
-                                                                ) {
-                                                                    score.hub_opaque_packets += 1;
-                                                                } else if self
-                                                                    .source_channels
-                                                                    .contains(channel)
-                                                                {
-                                                                    score.packets_from_hub += 1;
-                                                                } else {
-                                                                    score.opaque_packets_tx += 1;
-                                                                }
-
-                                                                // Use src channels as proxy for the number of packets in a multimessage
-                                                                score.opaque_packets_total +=
-                                                                    src_channels.len() as u64;
-                                                            }
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        IBCEvent::PacketTransfer(ref inner_event) => {
-                            status_ok!("Processing Packet Transfer", " got event");
-
-                            if let Some(config) = &self.config {
-                                if config.hub_id.contains(&envelope.network.to_string()) {
-                                    if let Some(dst_channels) =
-                                        inner_event.data.get("send_packet.packet_dst_channel")
-                                    {
-                                        for dst_channel in dst_channels {
-                                            /// Populate the source channels data
-                                            self.source_channels.insert(dst_channel.clone());
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-                _ => {
-                    continue;
-                }
-            }
+        self.activity.envelope(&envelope.network.to_string());
+
+        for phase in self.phases.iter_mut() {
+            phase.score_envelope(&envelope);
         }
     }
 
-    fn get_team_by_address(&self, address: &str) -> Option<&String> {
-        if address.contains("cosmos1") {
-            return self.address_to_team.get(address);
-        } else {
-            match decode(address) {
-                Ok((_, data)) => return self.address_to_team.get(&encode("cosmos", data)),
-                Err(_) => return None,
-            }
+    /// Metrics of the run in the Prometheus text format
+    pub fn metrics(&self) -> String {
+        metrics::render(&self.report(), &self.activity)
+    }
+
+    /// Count a posted envelope which couldn't be parsed
+    pub fn parse_failed(&mut self) {
+        self.activity.parse_failure();
+    }
+
+    /// Record an input line which couldn't be parsed
+    pub fn reject(&mut self, rejection: &Rejection) -> Result<(), Error> {
+        self.activity.parse_failure();
+        self.rejected += 1;
+        self.quarantine.append(rejection).map_err(|e| {
+            ErrorKind::Io
+                .context(format!("could not write to the quarantine: {}", e))
+                .into()
+        })
+    }
+
+    /// Number of input lines rejected so far
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+
+    /// Record that an input has been scored up to `position`
+    pub fn consumed(&mut self, input: &Path, position: Position) {
+        match self.inputs.iter_mut().find(|(path, _)| path == input) {
+            Some((_, consumed)) => *consumed = position,
+            None => self.inputs.push((input.to_owned(), position)),
         }
     }
+
+    /// Empty shard scoring the same phases, for scoring part of the inputs
+    /// on another thread
+    pub fn shard(&self) -> Shard {
+        Shard {
+            phases: self.phases.iter().map(|phase| phase.fork()).collect(),
+            rejections: Vec::new(),
+            activity: Activity::default(),
+        }
+    }
+
+    /// Add the events scored by a shard. Shards must be merged in the order
+    /// of their inputs for the results to match scoring them sequentially.
+    pub fn merge(&mut self, shard: Shard) -> Result<(), Error> {
+        for (phase, shard_phase) in self.phases.iter_mut().zip(shard.phases) {
+            phase.merge(shard_phase);
+        }
+
+        self.activity.merge(shard.activity);
+
+        for rejection in shard.rejections.iter() {
+            self.reject(rejection)?;
+        }
+
+        Ok(())
+    }
+
+    /// Serialized state of every phase, for a checkpoint
+    pub fn save_phases(&self) -> BTreeMap<String, serde_json::Value> {
+        self.phases
+            .iter()
+            .map(|phase| (phase.name().to_owned(), phase.save()))
+            .collect()
+    }
+
+    /// Restore every phase, the rejected line count and the inputs scored
+    /// from a checkpoint
+    pub fn restore(&mut self, checkpoint: &Checkpoint) -> Result<(), Error> {
+        self.rejected = checkpoint.rejected;
+        self.inputs = checkpoint
+            .inputs
+            .iter()
+            .map(|(path, position)| (path.clone(), *position))
+            .collect();
+
+        for phase in self.phases.iter_mut() {
+            let state = checkpoint.phases.get(phase.name()).ok_or_else(|| {
+                ErrorKind::Checkpoint.context(format!("no state saved for phase {}", phase.name()))
+            })?;
+
+            phase
+                .restore(state.clone())
+                .map_err(|e| ErrorKind::Checkpoint.context(e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Current leaderboard of every phase being scored
+    pub fn leaderboard(&self) -> BTreeMap<String, Vec<Standing>> {
+        self.report()
+            .phases
+            .into_iter()
+            .map(|phase| {
+                let standings = phase
+                    .teams
+                    .into_iter()
+                    .map(|team| Standing {
+                        rank: team.rank,
+                        team: team.team,
+                        score: team.total,
+                        gap: team.gap,
+                        percentile: team.percentile,
+                    })
+                    .collect();
+
+                (phase.phase, standings)
+            })
+            .collect()
+    }
+
+    /// Write every scoring decision made or changed since the last flush to
+    /// the audit ledger
+    pub fn flush_ledger(&mut self) -> Result<(), Error> {
+        for phase in self.phases.iter_mut() {
+            phase.flush_ledger(&mut self.ledger).map_err(|e| {
+                ErrorKind::Io.context(format!("could not write to the audit ledger: {}", e))
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Phases scoring part of the inputs, to be merged back into the
+/// application
+#[derive(Debug)]
+pub struct Shard {
+    phases: Vec<Box<dyn ScoringPhase>>,
+    rejections: Vec<Rejection>,
+    activity: Activity,
+}
+
+impl Shard {
+    /// Score every event in the envelope
+    pub fn score_envelope(&mut self, envelope: Envelope) {
+        self.activity.envelope(&envelope.network.to_string());
+
+        for phase in self.phases.iter_mut() {
+            phase.score_envelope(&envelope);
+        }
+    }
+
+    /// Record an input line which couldn't be parsed, to be quarantined when
+    /// the shard is merged
+    pub fn reject(&mut self, rejection: Rejection) {
+        self.rejections.push(rejection);
+    }
 }
 
 /// Initialize a new application instance.
@@ -245,11 +308,13 @@ impl Default for GozScoringApp {
     fn default() -> Self {
         Self {
             config: None,
-            scores: HashMap::new(),
+            phases: Vec::new(),
+            ledger: Ledger::default(),
+            quarantine: Quarantine::default(),
+            rejected: 0,
+            inputs: Vec::new(),
+            activity: Activity::default(),
             state: application::State::default(),
-            address_to_team: HashMap::new(),
-            source_channels: BTreeSet::new(),
-            observed_transactions: BTreeSet::new(),
         }
     }
 }
@@ -297,9 +362,45 @@ impl Application for GozScoringApp {
     fn after_config(&mut self, config: Self::Cfg) -> Result<(), FrameworkError> {
         // Configure components
         self.state.components.after_config(&config)?;
-        status_ok!("Config", "Build Hashmaps");
-        self.address_to_team = config.build_hashmaps();
-        dbg!(&self.address_to_team);
+        status_ok!("Config", "Build Phases");
+        self.phases = Vec::new();
+
+        for name in config.phases.iter() {
+            match phases::build(name, &config) {
+                Some(phase) => self.phases.push(phase),
+                None => {
+                    return Err(FrameworkErrorKind::ConfigError
+                        .context(format!(
+                            "unknown scoring phase {:?} (expected one of {:?})",
+                            name,
+                            phases::PHASE_NAMES
+                        ))
+                        .into())
+                }
+            }
+        }
+
+        if let Some(path) = &config.quarantine {
+            status_ok!("Config", "Quarantine {}", path.display());
+            self.quarantine = Quarantine::open(path).map_err(|e| {
+                FrameworkErrorKind::IoError.context(format!(
+                    "could not open quarantine {}: {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+        }
+
+        if let Some(path) = &config.ledger {
+            status_ok!("Config", "Audit ledger {}", path.display());
+            self.ledger = Ledger::open(path).map_err(|e| {
+                FrameworkErrorKind::IoError.context(format!(
+                    "could not open audit ledger {}: {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+        }
 
         self.config = Some(config);
 
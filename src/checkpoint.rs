@@ -0,0 +1,114 @@
+//! Scoring checkpoints
+//!
+//! A checkpoint is a snapshot of every phase's scoring state together with
+//! how much of each input has been consumed. `start --resume` loads it and
+//! only scores what was appended to the inputs since, instead of rescanning
+//! everything.
+//!
+//! Inputs are recorded with a digest of their first bytes, so an input
+//! replaced by another file since isn't read from an offset into the wrong
+//! file: a batch run refuses it, and following reads it from the start.
+
+use crate::error::{Error, ErrorKind};
+use crate::input::Position;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Version of the checkpoint format written by this binary. Checkpoints with
+/// any other version are refused.
+pub const VERSION: u32 = 5;
+
+/// Snapshot of the scoring state
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Checkpoint {
+    /// Format version
+    pub version: u32,
+    /// Serialized state of each phase, by phase name
+    pub phases: BTreeMap<String, serde_json::Value>,
+    /// How far each input has been consumed
+    pub inputs: BTreeMap<PathBuf, Position>,
+    /// Number of input lines rejected so far
+    pub rejected: u64,
+}
+
+impl Default for Checkpoint {
+    fn default() -> Self {
+        Self {
+            version: VERSION,
+            phases: BTreeMap::new(),
+            inputs: BTreeMap::new(),
+            rejected: 0,
+        }
+    }
+}
+
+impl Checkpoint {
+    /// Load the checkpoint at `path`
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let json: serde_json::Value = serde_json::from_slice(&fs::read(path)?)
+            .map_err(|e| ErrorKind::Checkpoint.context(e))?;
+
+        // Check the version before the rest of the format, which may differ
+        let version = json.get("version").and_then(serde_json::Value::as_u64);
+
+        if version != Some(u64::from(VERSION)) {
+            return Err(ErrorKind::Checkpoint
+                .context(format!(
+                    "unsupported checkpoint version {:?} (expected {})",
+                    version, VERSION
+                ))
+                .into());
+        }
+
+        Ok(serde_json::from_value(json).map_err(|e| ErrorKind::Checkpoint.context(e))?)
+    }
+
+    /// Write the checkpoint to `path`.
+    ///
+    /// It is written to a temporary file first so a crash never leaves a
+    /// partially written checkpoint behind.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+
+        let json = serde_json::to_vec(self).expect("checkpoint is serializable");
+        fs::write(&tmp_path, json)?;
+        fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+
+    /// How far `input` has already been consumed
+    pub fn position(&self, input: &Path) -> Position {
+        self.inputs.get(input).copied().unwrap_or_default()
+    }
+}
+
+/// Serialize a map as a list of `[key, value]` entries, for maps whose keys
+/// can't be JSON object keys
+pub(crate) mod entries {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::BTreeMap;
+
+    pub fn serialize<S, K, V>(map: &BTreeMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        K: Serialize,
+        V: Serialize,
+    {
+        serializer.collect_seq(map.iter())
+    }
+
+    pub fn deserialize<'de, D, K, V>(deserializer: D) -> Result<BTreeMap<K, V>, D::Error>
+    where
+        D: Deserializer<'de>,
+        K: Deserialize<'de> + Ord,
+        V: Deserialize<'de>,
+    {
+        Ok(Vec::<(K, V)>::deserialize(deserializer)?
+            .into_iter()
+            .collect())
+    }
+}
@@ -5,15 +5,23 @@
 //! The default application comes with two subcommands:
 //!
 //! - `start`: launches the application
+//! - `fetch`: builds envelopes from a chain's Tendermint RPC
+//! - `report`: renders the results saved in a checkpoint
+//! - `serve`: scores envelopes POSTed by sagan collectors over HTTP
 //! - `version`: print application version
 //!
 //! See the `impl Configurable` below for how to specify the path to the
 //! application's configuration file.
 
+mod fetch;
+mod report;
+mod serve;
 mod start;
 mod version;
 
-use self::{start::StartCmd, version::VersionCmd};
+use self::{
+    fetch::FetchCmd, report::ReportCmd, serve::ServeCmd, start::StartCmd, version::VersionCmd,
+};
 use crate::config::GozScoringConfig;
 use abscissa_core::{
     config::Override, Command, Configurable, FrameworkError, Help, Options, Runnable,
@@ -34,6 +42,18 @@ pub enum GozScoringCmd {
     #[options(help = "start the application")]
     Start(StartCmd),
 
+    /// The `fetch` subcommand
+    #[options(help = "build envelopes from a Tendermint RPC endpoint")]
+    Fetch(FetchCmd),
+
+    /// The `report` subcommand
+    #[options(help = "render the results saved in a checkpoint")]
+    Report(ReportCmd),
+
+    /// The `serve` subcommand
+    #[options(help = "score envelopes posted over HTTP")]
+    Serve(ServeCmd),
+
     /// The `version` subcommand
     #[options(help = "display version information")]
     Version(VersionCmd),
@@ -55,18 +75,14 @@ impl Configurable<GozScoringConfig> for GozScoringCmd {
         }
     }
 
-    // /// Apply changes to the config after it's been loaded, e.g. overriding
-    // /// values in a config file using command-line options.
-    // ///
-    // /// This can be safely deleted if you don't want to override config
-    // /// settings from command-line options.
-    // fn process_config(
-    //     &self,
-    //     config: GozScoringConfig,
-    // ) -> Result<GozScoringConfig, FrameworkError> {
-    //     match self {
-    //         GozScoringCmd::Start(cmd) => cmd.override_config(config),
-    //         _ => Ok(config),
-    //     }
-    // }
+    /// Apply changes to the config after it's been loaded, e.g. overriding
+    /// values in a config file using command-line options.
+    fn process_config(&self, config: GozScoringConfig) -> Result<GozScoringConfig, FrameworkError> {
+        match self {
+            GozScoringCmd::Start(cmd) => cmd.override_config(config),
+            GozScoringCmd::Report(cmd) => cmd.override_config(config),
+            GozScoringCmd::Serve(cmd) => cmd.override_config(config),
+            _ => Ok(config),
+        }
+    }
 }
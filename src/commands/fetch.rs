@@ -0,0 +1,146 @@
+//! `fetch` subcommand - build envelopes from a chain's Tendermint RPC
+
+use crate::output::{Output, Policy};
+use crate::prelude::*;
+use crate::rpc::RpcClient;
+use abscissa_core::{Command, Options, Runnable};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process;
+
+/// `fetch` subcommand
+///
+/// Writes an envelope for every IBC packet transaction in a height range as
+/// JSONL, which `start` scores like the events written by the collectors.
+/// An output file is only written once every envelope has been fetched.
+#[derive(Command, Debug, Options)]
+pub struct FetchCmd {
+    /// Tendermint RPC endpoint of the chain
+    #[options(help = "Tendermint RPC endpoint, e.g. http://localhost:26657")]
+    rpc: Option<String>,
+
+    /// Chain-id to put in the envelopes instead of the node's
+    #[options(no_short, help = "chain-id of the envelopes (default: the node's)")]
+    chain_id: Option<String>,
+
+    /// First height to fetch
+    #[options(help = "first height to fetch (default 1)")]
+    from_height: Option<u64>,
+
+    /// Last height to fetch
+    #[options(help = "last height to fetch (default: the latest)")]
+    to_height: Option<u64>,
+
+    /// Scan every block instead of searching the transaction index
+    #[options(help = "read block_results block by block, for nodes without a tx index")]
+    blocks: bool,
+
+    /// Path of the JSONL output
+    #[options(help = "write the envelopes to this file instead of stdout")]
+    output: Option<PathBuf>,
+
+    /// Replace the output file if it already exists
+    #[options(no_short, help = "replace the output file if it already exists")]
+    overwrite: bool,
+
+    /// Add the time to the name of the output file
+    #[options(no_short, help = "add the current time to the output file name")]
+    timestamp: bool,
+}
+
+impl FetchCmd {
+    /// What to do with an output file which already exists
+    fn policy(&self) -> Policy {
+        match (self.overwrite, self.timestamp) {
+            (false, false) => Policy::Refuse,
+            (true, false) => Policy::Overwrite,
+            (false, true) => Policy::Timestamp,
+            (true, true) => {
+                status_err!("--overwrite and --timestamp can't be used together");
+                process::exit(1);
+            }
+        }
+    }
+
+    /// Destination of the envelopes, if not stdout, exiting if they couldn't
+    /// be written there
+    fn output(&self) -> Option<Output> {
+        self.output.as_ref().map(|path| {
+            Output::open(path, self.policy()).unwrap_or_else(|e| {
+                status_err!("can't write envelopes to {}: {}", path.display(), e);
+                process::exit(1);
+            })
+        })
+    }
+}
+
+impl Runnable for FetchCmd {
+    /// Fetch the envelopes
+    fn run(&self) {
+        let url = self.rpc.as_deref().unwrap_or_else(|| {
+            status_err!("fetch needs an RPC endpoint, set with --rpc");
+            process::exit(1);
+        });
+
+        let client = RpcClient::new(url);
+
+        let (network, latest) = client.status().unwrap_or_else(|e| {
+            status_err!("could not get the status of {}: {}", url, e);
+            process::exit(1);
+        });
+
+        let chain_id = self.chain_id.clone().unwrap_or(network);
+        let from = self.from_height.unwrap_or(1);
+        let to = self.to_height.unwrap_or(latest);
+
+        let output = self.output();
+        let mut envelopes = String::new();
+        let mut stdout = io::stdout();
+        let mut written = Ok(());
+
+        // Envelopes for a file are kept until they can replace it at once
+        let write = |envelope| {
+            let line = serde_json::to_string(&envelope).expect("envelope is serializable") + "\n";
+
+            match output {
+                Some(_) => envelopes.push_str(&line),
+                None if written.is_ok() => written = stdout.write_all(line.as_bytes()),
+                None => (),
+            }
+        };
+
+        let fetched = if self.blocks {
+            client.scan_blocks(&chain_id, from, to, write)
+        } else {
+            client.search_txs(&chain_id, from, to, write)
+        };
+
+        let result = fetched.map_err(|e| e.to_string()).and_then(|count| {
+            let written = match &output {
+                Some(output) => output.write(&envelopes).map_err(|e| e.to_string()),
+                None => written
+                    .and_then(|()| stdout.flush())
+                    .map_err(|e| e.to_string()),
+            };
+
+            written
+                .map(|()| count)
+                .map_err(|e| format!("could not write envelopes: {}", e))
+        });
+
+        match result {
+            Ok(count) => status_ok!(
+                "Fetched",
+                "{} envelopes from {} heights {}-{}",
+                count,
+                chain_id,
+                from,
+                to
+            ),
+            Err(e) => {
+                status_err!("could not fetch from {}: {}", url, e);
+                process::exit(1);
+            }
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! `report` subcommand - render the results saved in a checkpoint
+
+use crate::checkpoint::Checkpoint;
+use crate::config::GozScoringConfig;
+use crate::output::{Output, Policy};
+use crate::prelude::*;
+use crate::report::{Format, TieBreaker};
+use abscissa_core::{config, Command, FrameworkError, Options, Runnable};
+use std::path::PathBuf;
+use std::process;
+
+/// `report` subcommand
+///
+/// Renders the results of a run from its checkpoint without scoring
+/// anything, so a run which is still following its inputs can be published
+/// at any time.
+#[derive(Command, Debug, Options)]
+pub struct ReportCmd {
+    /// Path of the checkpoint
+    #[options(help = "checkpoint to report the results of (default: the configured one)")]
+    checkpoint: Option<PathBuf>,
+
+    /// Format of the report
+    #[options(
+        no_short,
+        help = "report format: text (default), json, csv, markdown or html"
+    )]
+    format: Option<Format>,
+
+    /// Render a standalone HTML page
+    #[options(
+        no_short,
+        help = "render a self-contained HTML page, like --format html"
+    )]
+    html: bool,
+
+    /// How teams with the same total score are ranked
+    #[options(
+        no_short,
+        help = "rank tied teams: shared (default), earliest_height or name"
+    )]
+    tie_breaker: Option<TieBreaker>,
+
+    /// Path of the report
+    #[options(help = "write the report to this file instead of standard output")]
+    output: Option<PathBuf>,
+
+    /// Replace the report file if it already exists
+    #[options(no_short, help = "replace the report file if it already exists")]
+    overwrite: bool,
+}
+
+impl Runnable for ReportCmd {
+    /// Render the checkpointed results
+    fn run(&self) {
+        let format = match (self.html, self.format) {
+            (true, None) | (true, Some(Format::Html)) => Format::Html,
+            (true, Some(_)) => {
+                status_err!("--html and --format can't be used together");
+                process::exit(1);
+            }
+            (false, format) => format.unwrap_or_default(),
+        };
+
+        let output = self.output.as_ref().map(|path| {
+            let policy = if self.overwrite {
+                Policy::Overwrite
+            } else {
+                Policy::Refuse
+            };
+
+            Output::open(path, policy).unwrap_or_else(|e| {
+                status_err!("can't write the report to {}: {}", path.display(), e);
+                process::exit(1);
+            })
+        });
+
+        let path = app_config().checkpoint.clone().unwrap_or_else(|| {
+            status_err!("report needs a checkpoint, set with --checkpoint");
+            process::exit(1);
+        });
+
+        if let Err(e) =
+            Checkpoint::load(&path).and_then(|checkpoint| app_writer().restore(&checkpoint))
+        {
+            status_err!("could not load {}: {}", path.display(), e);
+            process::exit(1);
+        }
+
+        let report = app_reader().report().render(format);
+
+        match output {
+            Some(output) => {
+                if let Err(e) = output.write(&report) {
+                    status_err!("could not write {}: {}", output.path().display(), e);
+                    process::exit(1);
+                }
+
+                status_ok!("Reported", "{}", output.path().display());
+            }
+            None => print!("{}", report),
+        }
+    }
+}
+
+impl config::Override<GozScoringConfig> for ReportCmd {
+    // Process the given command line options, overriding settings from
+    // a configuration file using explicit flags taken from command-line
+    // arguments.
+    fn override_config(
+        &self,
+        mut config: GozScoringConfig,
+    ) -> Result<GozScoringConfig, FrameworkError> {
+        if let Some(checkpoint) = &self.checkpoint {
+            config.checkpoint = Some(checkpoint.clone());
+        }
+
+        if let Some(tie_breaker) = self.tie_breaker {
+            config.tie_breaker = tie_breaker;
+        }
+
+        Ok(config)
+    }
+}
@@ -0,0 +1,150 @@
+//! `serve` subcommand - score envelopes as sagan collectors post them
+
+use crate::config::GozScoringConfig;
+use crate::metrics;
+use crate::prelude::*;
+use abscissa_core::{config, Command, FrameworkError, Options, Runnable};
+use sagan::message::Envelope;
+use std::path::PathBuf;
+use std::process;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+/// Address the server listens on when `--listen` isn't given
+pub const DEFAULT_LISTEN_ADDR: &str = "127.0.0.1:3000";
+
+/// `serve` subcommand
+///
+/// Envelopes are POSTed to `/envelopes`, either one JSON document per
+/// request or several concatenated as JSONL. The live leaderboard is
+/// available as JSON at `/leaderboard`, the full report as text at
+/// `/report` and metrics for Prometheus at `/metrics`. The decisions made
+/// from posted envelopes are in the audit ledger before the POST is
+/// answered.
+#[derive(Command, Debug, Options)]
+pub struct ServeCmd {
+    /// Address to listen on
+    #[options(help = "address to listen on (default 127.0.0.1:3000)")]
+    listen: Option<String>,
+
+    /// Scoring phases to run instead of the configured ones
+    #[options(help = "scoring phase to run (may be repeated)")]
+    phase: Vec<String>,
+
+    /// Path of the JSONL audit ledger
+    #[options(no_short, help = "append every scoring decision to this JSONL file")]
+    ledger: Option<PathBuf>,
+}
+
+impl Runnable for ServeCmd {
+    /// Accept envelopes until the process is stopped
+    fn run(&self) {
+        app_config();
+
+        let listen = self.listen.as_deref().unwrap_or(DEFAULT_LISTEN_ADDR);
+
+        let server = Server::http(listen).unwrap_or_else(|e| {
+            status_err!("could not listen on {}: {}", listen, e);
+            process::exit(1);
+        });
+
+        status_ok!("Listening", "on http://{}", listen);
+
+        for mut request in server.incoming_requests() {
+            let response = match (request.method(), request.url()) {
+                (Method::Post, "/envelopes") => {
+                    let response = receive_envelopes(&mut request);
+                    flush_ledger();
+                    response
+                }
+                (Method::Get, "/leaderboard") => {
+                    let leaderboard = serde_json::to_string(&app_reader().leaderboard())
+                        .expect("leaderboard is serializable");
+
+                    Response::from_string(leaderboard).with_header(content_type("application/json"))
+                }
+                (Method::Get, "/report") => Response::from_string(app_reader().to_string())
+                    .with_header(content_type("text/plain; charset=utf-8")),
+                (Method::Get, "/metrics") => Response::from_string(app_reader().metrics())
+                    .with_header(metrics::content_type()),
+                (_, "/envelopes") | (_, "/leaderboard") | (_, "/report") | (_, "/metrics") => {
+                    Response::from_string("method not allowed").with_status_code(405)
+                }
+                _ => Response::from_string("not found").with_status_code(404),
+            };
+
+            if let Err(e) = request.respond(response) {
+                status_warn!("could not send response: {}", e);
+            }
+        }
+    }
+}
+
+/// Write new scoring decisions to the audit ledger, exiting if it can't be
+/// written rather than scoring envelopes it doesn't record
+fn flush_ledger() {
+    let flushed = app_writer().flush_ledger();
+
+    if let Err(e) = flushed {
+        status_err!("{}", e);
+        process::exit(1);
+    }
+}
+
+/// Score every envelope in the request body.
+///
+/// Envelopes before a malformed one are still scored.
+fn receive_envelopes(request: &mut Request) -> Response<std::io::Cursor<Vec<u8>>> {
+    let envelopes =
+        serde_json::Deserializer::from_reader(request.as_reader()).into_iter::<Envelope>();
+    let mut accepted = 0;
+
+    for envelope in envelopes {
+        match envelope {
+            Ok(envelope) => {
+                app_writer().score_envelope(envelope);
+                accepted += 1;
+            }
+            Err(e) => {
+                status_err!("Could not parse json {}", e);
+                app_writer().parse_failed();
+
+                return Response::from_string(format!(
+                    "could not parse envelope {}: {}",
+                    accepted + 1,
+                    e
+                ))
+                .with_status_code(400);
+            }
+        }
+    }
+
+    status_ok!("Received", "{} envelopes", accepted);
+
+    Response::from_string(format!("{{\"accepted\":{}}}", accepted))
+        .with_header(content_type("application/json"))
+}
+
+/// `Content-Type` header with the given value
+fn content_type(value: &str) -> Header {
+    Header::from_bytes(&b"Content-Type"[..], value.as_bytes()).expect("valid header")
+}
+
+impl config::Override<GozScoringConfig> for ServeCmd {
+    // Process the given command line options, overriding settings from
+    // a configuration file using explicit flags taken from command-line
+    // arguments.
+    fn override_config(
+        &self,
+        mut config: GozScoringConfig,
+    ) -> Result<GozScoringConfig, FrameworkError> {
+        if !self.phase.is_empty() {
+            config.phases = self.phase.clone();
+        }
+
+        if let Some(ledger) = &self.ledger {
+            config.ledger = Some(ledger.clone());
+        }
+
+        Ok(config)
+    }
+}
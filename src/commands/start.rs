@@ -1,19 +1,35 @@
 //! `start` subcommand - example of how to write a subcommand
 
-use crate::application::APPLICATION;
+use crate::application::Shard;
 /// App-local prelude includes `app_reader()`/`app_writer()`/`app_config()`
 /// accessors along with logging macros. Customize as you see fit.
 use crate::prelude::*;
 use std::fmt;
-use std::fs::File;
 use std::io::prelude::*;
-use std::io::{self, BufReader};
 
 use sagan::message::Envelope;
 
+use crate::checkpoint::Checkpoint;
 use crate::config::GozScoringConfig;
+use crate::error::{Error, ErrorKind};
+use crate::follow::FollowedFile;
+use crate::history::BucketBy;
+use crate::input::{self, Compression, Input, Position};
+use crate::manifest::{self, Manifest};
+use crate::metrics;
+use crate::output::{Output, Policy};
+use crate::quarantine::Rejection;
+use crate::report::{Format, TieBreaker};
 use abscissa_core::{config, Command, FrameworkError, Options, Runnable};
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
 
 /// `start` subcommand
 ///
@@ -24,37 +40,645 @@ use std::path::PathBuf;
 /// <https://docs.rs/gumdrop/>
 #[derive(Command, Debug, Options)]
 pub struct StartCmd {
-    /// To whom are we saying hello?
+    /// Event files, directories of event files or glob patterns to score
     #[options(free)]
     event_jsons: Vec<PathBuf>,
+
+    /// Path of a manifest listing more inputs
+    #[options(no_short, help = "also score the inputs listed in this TOML manifest")]
+    manifest: Option<PathBuf>,
+
+    /// Scoring phases to run instead of the configured ones
+    #[options(help = "scoring phase to run (may be repeated)")]
+    phase: Vec<String>,
+
+    /// Path of the JSONL audit ledger
+    #[options(help = "append every scoring decision to this JSONL file")]
+    ledger: Option<PathBuf>,
+
+    /// Keep reading the event files as the collectors append to them
+    #[options(help = "keep reading the files as they grow, like tail -F")]
+    follow: bool,
+
+    /// Seconds between reports in follow mode
+    #[options(help = "seconds between reports when following (default 60)")]
+    report_interval: Option<u64>,
+
+    /// Number of files scored in parallel
+    #[options(help = "number of files to score in parallel (default 1)")]
+    jobs: Option<usize>,
+
+    /// Path of the checkpoint
+    #[options(help = "save the scoring state to this file as inputs are consumed")]
+    checkpoint: Option<PathBuf>,
+
+    /// Resume from the checkpoint instead of starting over
+    #[options(no_short, help = "resume from the checkpoint, scoring only new data")]
+    resume: bool,
+
+    /// Path of the JSONL quarantine for unparseable lines
+    #[options(help = "append every unparseable input line to this JSONL file")]
+    quarantine: Option<PathBuf>,
+
+    /// Number of rejected lines above which the run fails
+    #[options(help = "exit non-zero if more input lines than this are rejected")]
+    max_rejected: Option<u64>,
+
+    /// Format of the results file
+    #[options(
+        no_short,
+        help = "results format: text (default), json, csv, markdown or html"
+    )]
+    format: Option<Format>,
+
+    /// How teams with the same total score are ranked
+    #[options(
+        no_short,
+        help = "rank tied teams: shared (default), earliest_height or name"
+    )]
+    tie_breaker: Option<TieBreaker>,
+
+    /// Path of the results file
+    #[options(help = "write the results to this file (default results.<format>)")]
+    output: Option<PathBuf>,
+
+    /// Replace the results file if it already exists
+    #[options(no_short, help = "replace the results file if it already exists")]
+    overwrite: bool,
+
+    /// Add the time to the name of the results file
+    #[options(no_short, help = "add the current time to the results file name")]
+    timestamp: bool,
+
+    /// Path of the score history
+    #[options(
+        no_short,
+        help = "also write each team's score over time to this file (CSV if .csv, else JSON)"
+    )]
+    history: Option<PathBuf>,
+
+    /// What the score history is bucketed by
+    #[options(
+        no_short,
+        help = "bucket the score history by height (default) or time (with --follow)"
+    )]
+    bucket_by: Option<BucketBy>,
+
+    /// Number of heights or seconds in each bucket of the score history
+    #[options(
+        no_short,
+        help = "heights or seconds in each history bucket (default 1000)"
+    )]
+    bucket_size: Option<u64>,
+
+    /// Address to serve metrics on
+    #[options(
+        no_short,
+        help = "serve metrics for Prometheus at /metrics on this address"
+    )]
+    metrics: Option<String>,
 }
 
+/// Seconds between reports in follow mode when `--report-interval` isn't given
+pub const DEFAULT_REPORT_INTERVAL: u64 = 60;
+
+/// How long to wait for the followed files to grow
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
 impl Runnable for StartCmd {
     /// Start the application.
     fn run(&self) {
-        app_config();
+        let output = self.output();
+        let history = self.history_output();
+        let inputs = self.inputs();
 
-        for scoreable in self.event_jsons.as_slice() {
-            let f = File::open(scoreable).expect(&format!("Could not open file {:?}", scoreable));
-            let f = BufReader::new(f);
-            for line in f.lines() {
-                let line = line.expect("Failed to read line");
+        if let Some(listen) = &self.metrics {
+            if let Err(e) = metrics::serve(listen) {
+                status_err!("could not serve metrics: {}", e);
+                process::exit(1);
+            }
 
-                match serde_json::from_str(&line) {
-                    Ok(envelope) => {
-                        status_ok!("Running", "processing envelope");
+            status_ok!("Metrics", "on http://{}/metrics", listen);
+        }
 
-                        let mut state = app_writer();
+        let mut progress = Progress {
+            checkpoint: Checkpoint::default(),
+            path: app_config().checkpoint.clone(),
+        };
 
-                        status_ok!("Running", "Got Lock");
+        if self.resume {
+            progress.resume();
+        }
 
-                        state.score_envelope(envelope);
+        if self.follow {
+            self.follow(&inputs, &output, history.as_ref(), &mut progress);
+        }
+
+        let scored = match self.jobs {
+            Some(jobs) if jobs > 1 => score_in_parallel(&inputs, jobs, &mut progress),
+            _ => inputs.iter().try_for_each(|input| {
+                let position = score_file(input, progress.checkpoint.position(&input.path))?;
+                progress.consumed(&input.path, position);
+                Ok(())
+            }),
+        };
+
+        if let Err(e) = scored {
+            self.stop(&output, history.as_ref(), e);
+        }
+
+        self.write_results(&output, history.as_ref());
+        check_rejected(app_config().max_rejected);
+    }
+}
+
+impl StartCmd {
+    /// Format of the results file
+    fn format(&self) -> Format {
+        self.format.unwrap_or_default()
+    }
+
+    /// What to do with results and history files which already exist
+    fn policy(&self) -> Policy {
+        match (self.overwrite, self.timestamp) {
+            (false, false) => Policy::Refuse,
+            (true, false) => Policy::Overwrite,
+            (false, true) => Policy::Timestamp,
+            (true, true) => {
+                status_err!("--overwrite and --timestamp can't be used together");
+                process::exit(1);
+            }
+        }
+    }
+
+    /// Destination of the results, exiting if they couldn't be written there
+    fn output(&self) -> Output {
+        let path = self
+            .output
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(format!("results.{}", self.format().extension())));
+
+        Output::open(&path, self.policy()).unwrap_or_else(|e| {
+            status_err!("can't write results to {}: {}", path.display(), e);
+            process::exit(1);
+        })
+    }
+
+    /// Destination of the score history, if one was asked for, exiting if
+    /// it couldn't be written there or its buckets can't be filled
+    fn history_output(&self) -> Option<Output> {
+        self.history.as_ref().map(|path| {
+            // Everything a backfill scores is scored at once, so it would
+            // all fall in the same time bucket
+            if app_config().history.by == BucketBy::Time && !self.follow {
+                status_err!("time buckets need --follow, bucket the score history by height");
+                process::exit(1);
+            }
+
+            Output::open(path, self.policy()).unwrap_or_else(|e| {
+                status_err!("can't write the score history to {}: {}", path.display(), e);
+                process::exit(1);
+            })
+        })
+    }
+
+    /// Write the results and the score history, printing the results if
+    /// they couldn't be written so they aren't lost
+    fn write_results(&self, output: &Output, history: Option<&Output>) {
+        let report = app_reader().report().render(self.format());
+
+        if let Err(e) = output.write(&report) {
+            status_err!("could not write {}: {}", output.path().display(), e);
+            print!("{}", report);
+            process::exit(1);
+        }
+
+        status_ok!("Reported", "{}", output.path().display());
+
+        if let Some(history) = history {
+            write_history(history);
+        }
+    }
+
+    /// Stop scoring after an input couldn't be read or the audit ledger or
+    /// quarantine couldn't be written, writing the results scored so far
+    /// before exiting with an error
+    fn stop(&self, output: &Output, history: Option<&Output>, error: Error) -> ! {
+        self.write_results(output, history);
+        status_err!("scoring stopped: {}", error);
+        process::exit(1);
+    }
+
+    /// Event files named by the command line and the manifest, exiting if
+    /// any of them doesn't match its checksum
+    fn inputs(&self) -> Vec<Input> {
+        let manifest = match &self.manifest {
+            Some(path) => Manifest::load(path).unwrap_or_else(|e| {
+                status_err!("could not load manifest {}: {}", path.display(), e);
+                process::exit(1);
+            }),
+            None => Manifest::default(),
+        };
+
+        let inputs = manifest::resolve(&self.event_jsons, &manifest).unwrap_or_else(|e| {
+            status_err!("could not resolve inputs: {}", e);
+            process::exit(1);
+        });
+
+        for input in inputs.iter() {
+            if let Err(e) = input.verify() {
+                status_err!("could not verify {}: {}", input.path.display(), e);
+                process::exit(1);
+            }
+        }
+
+        inputs
+    }
+
+    /// Score the event files as they grow, replacing the results file with a
+    /// new report every `--report-interval` seconds while there are new events.
+    /// Runs until the process is stopped.
+    fn follow(
+        &self,
+        inputs: &[Input],
+        output: &Output,
+        history: Option<&Output>,
+        progress: &mut Progress,
+    ) -> ! {
+        let interval = Duration::from_secs(self.report_interval.unwrap_or(DEFAULT_REPORT_INTERVAL));
+        let mut files = Vec::new();
+
+        // Compressed archives don't grow, so they are only scored once
+        for input in inputs.iter() {
+            let position = progress.checkpoint.position(&input.path);
+
+            match Compression::of(&input.path) {
+                Ok(Compression::Gzip) | Ok(Compression::Zstd) => {
+                    match score_file(input, position) {
+                        Ok(position) => progress.consumed(&input.path, position),
+                        Err(e) => self.stop(output, history, e),
+                    }
+                }
+                _ => files.push((input, FollowedFile::new(&input.path).resume_at(position))),
+            }
+        }
+
+        let mut last_report: Option<Instant> = None;
+        let mut changed = true;
+
+        loop {
+            let mut scored = false;
+
+            for (input, file) in files.iter_mut() {
+                match file.poll() {
+                    Ok(lines) => {
+                        if lines.is_empty() {
+                            continue;
+                        }
+
+                        let consumed = lines
+                            .iter()
+                            .try_for_each(|(number, line)| score_line(input, *number, line))
+                            .and_then(|()| app_writer().flush_ledger());
+
+                        if let Err(e) = consumed {
+                            self.stop(output, history, e);
+                        }
+
+                        scored = true;
                     }
-                    Err(e) => status_err!("Could not parse json {}", e),
+                    Err(e) => status_warn!("could not read {}: {}", file.path().display(), e),
+                }
+            }
+
+            changed |= scored;
+
+            let due = match last_report {
+                Some(reported) => reported.elapsed() >= interval,
+                None => true,
+            };
+
+            if changed && due {
+                let report = app_reader().report().render(self.format());
+
+                match output.write(&report) {
+                    Ok(()) => status_ok!("Reported", "{}", output.path().display()),
+                    Err(e) => status_err!("could not write {}: {}", output.path().display(), e),
+                }
+
+                if let Some(history) = history {
+                    write_history(history);
+                }
+
+                for (_, file) in files.iter() {
+                    progress.record(file.path(), file.position());
+                }
+
+                progress.save();
+                check_rejected(app_config().max_rejected);
+
+                last_report = Some(Instant::now());
+                changed = false;
+            }
+
+            if !scored {
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+    }
+}
+
+/// Inputs consumed so far, checkpointed along with the scoring state
+struct Progress {
+    checkpoint: Checkpoint,
+    path: Option<PathBuf>,
+}
+
+impl Progress {
+    /// Restore the scoring state and input offsets from the checkpoint
+    fn resume(&mut self) {
+        let path = self.path.clone().unwrap_or_else(|| {
+            status_err!("--resume needs a checkpoint, set with --checkpoint");
+            process::exit(1);
+        });
+
+        self.checkpoint = Checkpoint::load(&path)
+            .and_then(|checkpoint| {
+                app_writer().restore(&checkpoint)?;
+                Ok(checkpoint)
+            })
+            .unwrap_or_else(|e| {
+                status_err!("could not resume from {}: {}", path.display(), e);
+                process::exit(1);
+            });
+
+        status_ok!("Resumed", "from {}", path.display());
+    }
+
+    /// Record the position an input has been consumed up to, without saving
+    fn record(&mut self, input: &Path, position: Position) {
+        self.checkpoint.inputs.insert(input.to_owned(), position);
+        app_writer().consumed(input, position);
+    }
+
+    /// Record that an input has been consumed up to `position` and save the
+    /// checkpoint
+    fn consumed(&mut self, input: &Path, position: Position) {
+        self.record(input, position);
+        self.save();
+    }
+
+    /// Save the scoring state and input offsets, if checkpointing
+    fn save(&mut self) {
+        if let Some(path) = &self.path {
+            let state = app_reader();
+            self.checkpoint.phases = state.save_phases();
+            self.checkpoint.rejected = state.rejected();
+            drop(state);
+
+            if let Err(e) = self.checkpoint.save(path) {
+                status_err!("could not write checkpoint {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Score each file into its own shard on one of `jobs` worker threads.
+///
+/// Shards are merged in the order of the inputs, flushing the ledger after
+/// each one, so the results and the ledger are identical to scoring the
+/// files one after the other. Stops merging if a file can't be read or the
+/// ledger or quarantine can't be written.
+fn score_in_parallel(inputs: &[Input], jobs: usize, progress: &mut Progress) -> Result<(), Error> {
+    let inputs: Arc<Vec<(Input, Position)>> = Arc::new(
+        inputs
+            .iter()
+            .map(|input| (input.clone(), progress.checkpoint.position(&input.path)))
+            .collect(),
+    );
+    let next = Arc::new(AtomicUsize::new(0));
+    let (sender, receiver) = mpsc::channel();
+
+    let workers: Vec<_> = (0..jobs.min(inputs.len()))
+        .map(|_| {
+            let inputs = Arc::clone(&inputs);
+            let next = Arc::clone(&next);
+            let sender = sender.clone();
+
+            thread::spawn(move || loop {
+                let index = next.fetch_add(1, atomic::Ordering::SeqCst);
+
+                let (input, position) = match inputs.get(index) {
+                    Some(input) => input,
+                    None => return,
+                };
+
+                if sender.send((index, score_shard(input, *position))).is_err() {
+                    return;
                 }
+            })
+        })
+        .collect();
+
+    drop(sender);
+
+    // Shards finished ahead of an earlier file wait for it
+    let mut finished = BTreeMap::new();
+    let mut next_merge = 0;
+    let mut merged = Ok(());
+
+    'merge: for (index, shard) in receiver.iter() {
+        finished.insert(index, shard);
+
+        while let Some(shard) = finished.remove(&next_merge) {
+            merged = shard
+                .and_then(|(shard, position)| {
+                    let mut state = app_writer();
+                    state.merge(shard)?;
+                    state.flush_ledger()?;
+                    Ok(position)
+                })
+                .map(|position| progress.consumed(&inputs[next_merge].0.path, position));
+
+            if merged.is_err() {
+                break 'merge;
             }
+
+            next_merge += 1;
+        }
+    }
+
+    // Workers stop at their next file once their shards can't be sent
+    drop(receiver);
+
+    for worker in workers {
+        if let Err(panic) = worker.join() {
+            panic::resume_unwind(panic);
+        }
+    }
+
+    merged
+}
+
+/// Call `score` with the number and content of every complete line of an
+/// event file after `position`, returning the position after the last one.
+///
+/// A last line without a newline may still be being written by a collector,
+/// so it is left for a later run, like when following the file. Lines are
+/// passed as bytes, as they may not be valid UTF-8. A file which is shorter
+/// than `position` or has been replaced since is refused.
+fn read_lines(
+    path: &Path,
+    position: Position,
+    mut score: impl FnMut(u64, &[u8]) -> Result<(), Error>,
+) -> Result<Position, Error> {
+    let (mut f, mut head) = input::open_at(path, position)
+        .map_err(|e| ErrorKind::Io.context(format!("could not open {}: {}", path.display(), e)))?;
+    let Position {
+        mut offset,
+        line: mut number,
+        ..
+    } = position;
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+
+        let read = f.read_until(b'\n', &mut line).map_err(|e| {
+            ErrorKind::Io.context(format!("could not read {}: {}", path.display(), e))
+        })?;
+
+        if read == 0 {
+            return Ok(head.position(offset, number));
+        }
+
+        if !line.ends_with(b"\n") {
+            status_warn!(
+                "{}: line {} has no newline yet and was left for a later run",
+                path.display(),
+                number + 1
+            );
+            return Ok(head.position(offset, number));
+        }
+
+        head.push(&line);
+        offset += read as u64;
+        number += 1;
+        score(number, input::trim_line_ending(&line))?;
+    }
+}
+
+/// Score every line of an event file after `position`, decompressing it if
+/// needed, and return the position of its end
+fn score_file(input: &Input, position: Position) -> Result<Position, Error> {
+    let position = read_lines(&input.path, position, |number, line| {
+        score_line(input, number, line)
+    })?;
+    app_writer().flush_ledger()?;
+    Ok(position)
+}
+
+/// Score every line of an event file after `position` into a new shard
+fn score_shard(input: &Input, position: Position) -> Result<(Shard, Position), Error> {
+    let mut shard = app_reader().shard();
+
+    let position = read_lines(&input.path, position, |number, line| {
+        match parse_line(input, number, line) {
+            Ok(envelope) => shard.score_envelope(envelope),
+            Err(rejection) => shard.reject(rejection),
+        }
+
+        Ok(())
+    })?;
+
+    Ok((shard, position))
+}
+
+/// Score a single JSONL line of an event file, quarantining it if it can't
+/// be parsed
+fn score_line(input: &Input, number: u64, line: &[u8]) -> Result<(), Error> {
+    match parse_line(input, number, line) {
+        Ok(envelope) => {
+            status_ok!("Running", "processing envelope");
+
+            let mut state = app_writer();
+
+            status_ok!("Running", "Got Lock");
+
+            state.score_envelope(envelope);
+            Ok(())
+        }
+        Err(rejection) => app_writer().reject(&rejection),
+    }
+}
+
+/// Parse a single JSONL line of an event file, checking it comes from the
+/// input's chain if it has one
+fn parse_line(input: &Input, number: u64, line: &[u8]) -> Result<Envelope, Rejection> {
+    let reject = |error: String| Rejection {
+        file: input.path.clone(),
+        line: number,
+        error,
+        content: String::from_utf8_lossy(line).into_owned(),
+    };
+
+    let line = str::from_utf8(line).map_err(|e| {
+        status_err!(
+            "line {} of {} isn't UTF-8: {}",
+            number,
+            input.path.display(),
+            e
+        );
+        reject(e.to_string())
+    })?;
+
+    let envelope: Envelope = serde_json::from_str(line).map_err(|e| {
+        status_err!("Could not parse json {}", e);
+        reject(e.to_string())
+    })?;
+
+    if let Some(chain_id) = &input.chain_id {
+        let network = envelope.network.to_string();
+
+        if network != *chain_id {
+            status_err!("envelope from {} in an input of {}", network, chain_id);
+            return Err(reject(format!(
+                "envelope from {}, expected {} by the manifest",
+                network, chain_id
+            )));
+        }
+    }
+
+    Ok(envelope)
+}
+
+/// Replace the score history, as CSV if its file name ends in `.csv` and
+/// as JSON otherwise
+fn write_history(history: &Output) {
+    let format = match history.path().extension() {
+        Some(extension) if extension == "csv" => Format::Csv,
+        _ => Format::Json,
+    };
+
+    match history.write(&app_reader().history().render(format)) {
+        Ok(()) => status_ok!("History", "{}", history.path().display()),
+        Err(e) => status_err!("could not write {}: {}", history.path().display(), e),
+    }
+}
+
+/// Exit with an error if more input lines were rejected than allowed
+fn check_rejected(max_rejected: Option<u64>) {
+    let rejected = app_reader().rejected();
+
+    if let Some(max_rejected) = max_rejected {
+        if rejected > max_rejected {
+            status_err!(
+                "{} input lines were rejected, more than the {} allowed",
+                rejected,
+                max_rejected
+            );
+            process::exit(1);
         }
-        APPLICATION.read().print();
     }
 }
 
@@ -66,6 +690,38 @@ impl config::Override<GozScoringConfig> for StartCmd {
         &self,
         mut config: GozScoringConfig,
     ) -> Result<GozScoringConfig, FrameworkError> {
+        if !self.phase.is_empty() {
+            config.phases = self.phase.clone();
+        }
+
+        if let Some(ledger) = &self.ledger {
+            config.ledger = Some(ledger.clone());
+        }
+
+        if let Some(checkpoint) = &self.checkpoint {
+            config.checkpoint = Some(checkpoint.clone());
+        }
+
+        if let Some(quarantine) = &self.quarantine {
+            config.quarantine = Some(quarantine.clone());
+        }
+
+        if let Some(max_rejected) = self.max_rejected {
+            config.max_rejected = Some(max_rejected);
+        }
+
+        if let Some(tie_breaker) = self.tie_breaker {
+            config.tie_breaker = tie_breaker;
+        }
+
+        if let Some(bucket_by) = self.bucket_by {
+            config.history.by = bucket_by;
+        }
+
+        if let Some(bucket_size) = self.bucket_size {
+            config.history.size = bucket_size;
+        }
+
         Ok(config)
     }
 }
@@ -4,9 +4,13 @@
 //! application's configuration file and/or command-line options
 //! for specifying it.
 
+use crate::history::Buckets;
+use crate::phases::{Phase2OpaquePackets, SkipReason};
+use crate::report::TieBreaker;
 use sagan::config::collector::Team;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
 
 /// GozScoring Configuration
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -14,10 +18,132 @@ use std::collections::HashMap;
 pub struct GozScoringConfig {
     /// Chain-id of the Hub
     pub hub_id: Vec<String>,
+    /// Scoring phases to run
+    #[serde(default = "default_phases")]
+    pub phases: Vec<String>,
+    /// Path of the JSONL audit ledger, if one should be written
+    #[serde(default)]
+    pub ledger: Option<PathBuf>,
+    /// Path of the checkpoint written while scoring, if one should be kept
+    #[serde(default)]
+    pub checkpoint: Option<PathBuf>,
+    /// Path of the JSONL file unparseable input lines are written to
+    #[serde(default)]
+    pub quarantine: Option<PathBuf>,
+    /// Number of rejected input lines above which a run fails
+    #[serde(default)]
+    pub max_rejected: Option<u64>,
+    /// Block heights during which each phase was open, by phase name
+    #[serde(default)]
+    pub windows: BTreeMap<String, PhaseWindow>,
+    /// Weights used to compute each team's total score
+    #[serde(default)]
+    pub scoring: ScoringConfig,
+    /// How teams with the same total score are ranked
+    #[serde(default)]
+    pub tie_breaker: TieBreaker,
+    /// Chain whose block heights order tied teams and bucket the score
+    /// history, the last Hub chain-id if not set. Heights of different
+    /// chains can't be compared, so packets are placed by the height they
+    /// were received, sent or acknowledged at on this chain.
+    #[serde(default)]
+    pub reference_chain: Option<String>,
+    /// Buckets the score history is recorded in
+    #[serde(default)]
+    pub history: Buckets,
     /// An example configuration section
     pub teams: Vec<Team>,
 }
 
+fn default_phases() -> Vec<String> {
+    vec![Phase2OpaquePackets::NAME.to_owned()]
+}
+
+/// Block heights during which a phase was open.
+///
+/// Transaction events carry their block height but not the block time, so
+/// windows are expressed in heights. Either bound may be left open.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PhaseWindow {
+    /// First height scored on every chain
+    pub start_height: Option<u64>,
+    /// Last height scored on every chain
+    pub end_height: Option<u64>,
+    /// Bounds for individual chain-ids, overriding the ones above
+    #[serde(default)]
+    pub chains: BTreeMap<String, HeightRange>,
+}
+
+/// Inclusive range of block heights
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct HeightRange {
+    /// First height in the range
+    pub start_height: Option<u64>,
+    /// Last height in the range
+    pub end_height: Option<u64>,
+}
+
+impl PhaseWindow {
+    /// Check whether an event at `height` on `chain_id` falls inside the
+    /// window
+    pub fn check(&self, chain_id: &str, height: Option<u64>) -> Result<(), SkipReason> {
+        let chain = self.chains.get(chain_id).copied().unwrap_or_default();
+        let start_height = chain.start_height.or(self.start_height);
+        let end_height = chain.end_height.or(self.end_height);
+
+        if start_height.is_none() && end_height.is_none() {
+            return Ok(());
+        }
+
+        let height = height.ok_or(SkipReason::MissingHeight)?;
+
+        if let Some(start_height) = start_height {
+            if height < start_height {
+                return Err(SkipReason::BeforeWindow);
+            }
+        }
+
+        if let Some(end_height) = end_height {
+            if height > end_height {
+                return Err(SkipReason::AfterWindow);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Per-category scoring weights
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct ScoringConfig {
+    /// Weight of an opaque packet received on the Hub
+    pub hub_opaque_packets: f64,
+    /// Weight of an opaque packet received from a channel the Hub sends on
+    pub packets_from_hub: f64,
+    /// Weight of any other opaque packet
+    pub opaque_packets_tx: f64,
+    /// Optional weight applied to every packet relayed
+    pub opaque_packets_total: Option<f64>,
+    /// Weight of a packet whose acknowledgement was relayed back by the
+    /// same team which delivered it
+    pub round_trips: f64,
+}
+
+impl Default for ScoringConfig {
+    fn default() -> Self {
+        Self {
+            hub_opaque_packets: 1.0,
+            packets_from_hub: 0.5,
+            opaque_packets_tx: 0.1,
+            opaque_packets_total: None,
+            round_trips: 0.5,
+        }
+    }
+}
+
 impl GozScoringConfig {
     pub fn build_hashmaps(&self) -> HashMap<String, String> {
         let mut address_to_team = HashMap::new();
@@ -31,6 +157,22 @@ impl GozScoringConfig {
 
         return address_to_team;
     }
+
+    /// Chain whose block heights are compared across teams
+    pub fn reference_chain(&self) -> Option<&str> {
+        self.reference_chain
+            .as_deref()
+            .or_else(|| self.hub_id.last().map(String::as_str))
+    }
+
+    /// Map from the client-id each team registered on the Hub to the team
+    /// name
+    pub fn build_client_hashmap(&self) -> HashMap<String, String> {
+        self.teams
+            .iter()
+            .map(|team| (team.client_id.clone(), team.name.clone()))
+            .collect()
+    }
 }
 
 /// Default configuration settings.
@@ -41,6 +183,16 @@ impl Default for GozScoringConfig {
     fn default() -> Self {
         Self {
             hub_id: Vec::new(),
+            phases: default_phases(),
+            ledger: None,
+            checkpoint: None,
+            quarantine: None,
+            max_rejected: None,
+            windows: BTreeMap::new(),
+            scoring: ScoringConfig::default(),
+            tie_breaker: TieBreaker::default(),
+            reference_chain: None,
+            history: Buckets::default(),
             teams: Vec::new(),
         }
     }
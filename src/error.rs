@@ -18,6 +18,18 @@ pub enum ErrorKind {
     /// Input/output error
     #[error("I/O error")]
     Io,
+
+    /// Malformed or incompatible checkpoint
+    #[error("checkpoint error")]
+    Checkpoint,
+
+    /// Failed or malformed Tendermint RPC request
+    #[error("RPC error")]
+    Rpc,
+
+    /// Malformed input manifest or an input not matching it
+    #[error("manifest error")]
+    Manifest,
 }
 
 impl ErrorKind {
@@ -0,0 +1,378 @@
+//! Following growing event files
+//!
+//! Sagan collectors keep appending envelopes to their event files. A
+//! [`FollowedFile`] returns the lines appended since it was last polled,
+//! like `tail -F`: a file which is truncated is read again from the start,
+//! and a file which is rotated (replaced by a new file at the same path) is
+//! read to its end before switching to the new file. A file replaced while
+//! it wasn't followed is read from the start too, as the position it was
+//! consumed up to belongs to the file it replaced.
+
+use crate::input::{self, Head, Position};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+use std::mem;
+use std::path::{Path, PathBuf};
+
+/// An event file being followed
+#[derive(Debug)]
+pub struct FollowedFile {
+    /// Path of the file
+    path: PathBuf,
+
+    /// Currently open file, if the path has existed
+    reader: Option<BufReader<File>>,
+
+    /// Identity of the open file, used to notice rotation
+    id: Option<FileId>,
+
+    /// Bytes read from the open file
+    offset: u64,
+
+    /// Complete lines read from the open file
+    line: u64,
+
+    /// First bytes of the open file, identifying it in positions
+    head: Head,
+
+    /// Position to resume reading from when the file is first opened
+    resume_at: Position,
+
+    /// Trailing line which hasn't been terminated yet
+    partial: Vec<u8>,
+
+    /// Error hit after some lines were read, returned by the next poll
+    failed: Option<io::Error>,
+}
+
+impl FollowedFile {
+    /// Follow the file at `path`, which doesn't need to exist yet
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            reader: None,
+            id: None,
+            offset: 0,
+            line: 0,
+            head: Head::default(),
+            resume_at: Position::default(),
+            partial: Vec::new(),
+            failed: None,
+        }
+    }
+
+    /// Skip the part of the file consumed by an earlier run. A file shorter
+    /// than `position` or starting differently was truncated or replaced
+    /// since, and is read from the start.
+    pub fn resume_at(mut self, position: Position) -> Self {
+        self.resume_at = position;
+        self
+    }
+
+    /// Complete lines consumed from the file currently at the path
+    pub fn position(&self) -> Position {
+        match self.reader {
+            Some(_) => self
+                .head
+                .position(self.offset - self.partial.len() as u64, self.line),
+            None => self.resume_at,
+        }
+    }
+
+    /// Path of the file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Every complete line appended since the last poll, with its line
+    /// number.
+    ///
+    /// A line is only returned once its newline has been written, so a
+    /// collector caught in the middle of a write is picked up on the next
+    /// poll. Lines are returned as bytes, as they may not be valid UTF-8.
+    ///
+    /// The position has already moved past the lines returned, so an error
+    /// hit after some lines were read is returned by the next poll instead
+    /// of dropping them.
+    pub fn poll(&mut self) -> io::Result<Vec<(u64, Vec<u8>)>> {
+        if let Some(e) = self.failed.take() {
+            return Err(e);
+        }
+
+        let mut lines = Vec::new();
+
+        match self.read_appended(&mut lines) {
+            Ok(()) => Ok(lines),
+            Err(e) if lines.is_empty() => Err(e),
+            Err(e) => {
+                self.failed = Some(e);
+                Ok(lines)
+            }
+        }
+    }
+
+    /// Read the lines appended since the last poll into `lines`
+    fn read_appended(&mut self, lines: &mut Vec<(u64, Vec<u8>)>) -> io::Result<()> {
+        if self.reader.is_none() && !self.reopen()? {
+            return Ok(());
+        }
+
+        // Finish the file currently open before switching to a new file at
+        // the same path, so nothing written before rotation is lost
+        if self.rotated() {
+            self.read_lines(lines)?;
+
+            // The rotated file won't grow any more
+            if !self.partial.is_empty() {
+                self.line += 1;
+                lines.push((self.line, input::trim_line_ending(&self.partial).to_vec()));
+                self.partial.clear();
+            }
+
+            if !self.reopen()? {
+                return Ok(());
+            }
+        }
+
+        if self.truncated()? {
+            self.reader
+                .as_mut()
+                .expect("file is open")
+                .seek(SeekFrom::Start(0))?;
+            self.offset = 0;
+            self.line = 0;
+            self.head = Head::default();
+            self.partial.clear();
+        }
+
+        self.read_lines(lines)
+    }
+
+    /// Open the file at the path, returning `false` if it doesn't exist
+    fn reopen(&mut self) -> io::Result<bool> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+
+        let metadata = file.metadata()?;
+        let resume_at = mem::take(&mut self.resume_at);
+        let mut reader = BufReader::new(file);
+
+        let head = if metadata.len() >= resume_at.offset {
+            match Head::read(&mut reader, resume_at) {
+                Ok(head) => Some(head),
+                Err(ref e) if e.kind() == io::ErrorKind::InvalidData => None,
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => None,
+                Err(e) => return Err(e),
+            }
+        } else {
+            None
+        };
+
+        match head {
+            Some(head) => {
+                self.offset = reader.seek(SeekFrom::Start(resume_at.offset))?;
+                self.line = resume_at.line;
+                self.head = head;
+            }
+            None => {
+                self.offset = reader.seek(SeekFrom::Start(0))?;
+                self.line = 0;
+                self.head = Head::default();
+            }
+        }
+
+        self.id = FileId::of(&metadata);
+        self.reader = Some(reader);
+
+        Ok(true)
+    }
+
+    /// Has the path been replaced by a different file?
+    ///
+    /// A path which no longer exists hasn't been rotated yet; the open file
+    /// keeps being read until a new file appears.
+    fn rotated(&self) -> bool {
+        match fs::metadata(&self.path) {
+            Ok(metadata) => FileId::of(&metadata) != self.id,
+            Err(_) => false,
+        }
+    }
+
+    /// Has the open file been truncated below what was already read?
+    fn truncated(&self) -> io::Result<bool> {
+        let reader = self.reader.as_ref().expect("file is open");
+
+        Ok(reader.get_ref().metadata()?.len() < self.offset)
+    }
+
+    /// Read every complete line up to the end of the open file
+    fn read_lines(&mut self, lines: &mut Vec<(u64, Vec<u8>)>) -> io::Result<()> {
+        let reader = self.reader.as_mut().expect("file is open");
+
+        loop {
+            // Bytes consumed before an error are kept in the partial line
+            let before = self.partial.len();
+            let read = reader.read_until(b'\n', &mut self.partial);
+            self.offset += (self.partial.len() - before) as u64;
+            self.head.push(&self.partial[before..]);
+
+            if read? == 0 {
+                return Ok(());
+            }
+
+            if self.partial.ends_with(b"\n") {
+                let line = input::trim_line_ending(&self.partial).to_vec();
+                self.partial.clear();
+                self.line += 1;
+                lines.push((self.line, line));
+            }
+        }
+    }
+}
+
+/// Device and inode of a file
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct FileId {
+    dev: u64,
+    ino: u64,
+}
+
+impl FileId {
+    #[cfg(unix)]
+    fn of(metadata: &fs::Metadata) -> Option<Self> {
+        use std::os::unix::fs::MetadataExt;
+
+        Some(Self {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+        })
+    }
+
+    /// Rotation can't be detected without inodes, only truncation
+    #[cfg(not(unix))]
+    fn of(_metadata: &fs::Metadata) -> Option<Self> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::{env, process};
+
+    /// Path of an event file in an empty directory for the test
+    fn scratch_file(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("goz_scoring_follow_{}_{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("temporary directory is writable");
+        dir.join("events.jsonl")
+    }
+
+    fn append(path: &Path, data: &str) {
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(data.as_bytes()))
+            .expect("event file is writable");
+    }
+
+    fn poll(file: &mut FollowedFile) -> Vec<(u64, String)> {
+        file.poll()
+            .expect("event file is readable")
+            .into_iter()
+            .map(|(number, line)| (number, String::from_utf8(line).unwrap()))
+            .collect()
+    }
+
+    /// Offset and line the file has been consumed up to
+    fn consumed(file: &FollowedFile) -> (u64, u64) {
+        let position = file.position();
+        (position.offset, position.line)
+    }
+
+    fn lines(expected: &[(u64, &str)]) -> Vec<(u64, String)> {
+        expected
+            .iter()
+            .map(|(number, line)| (*number, (*line).to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn partial_lines_wait_for_their_newline() {
+        let path = scratch_file("partial");
+        let mut file = FollowedFile::new(&path);
+
+        assert!(poll(&mut file).is_empty());
+
+        append(&path, "one\ntw");
+        assert_eq!(poll(&mut file), lines(&[(1, "one")]));
+        assert_eq!(consumed(&file), (4, 1));
+
+        append(&path, "o\r\nthree\n");
+        assert_eq!(poll(&mut file), lines(&[(2, "two"), (3, "three")]));
+        assert_eq!(consumed(&file), (15, 3));
+        assert!(poll(&mut file).is_empty());
+    }
+
+    #[test]
+    fn truncated_files_are_read_from_the_start() {
+        let path = scratch_file("truncated");
+        append(&path, "one\ntwo\n");
+
+        let mut file = FollowedFile::new(&path);
+        assert_eq!(poll(&mut file), lines(&[(1, "one"), (2, "two")]));
+
+        fs::write(&path, "new\n").unwrap();
+        assert_eq!(poll(&mut file), lines(&[(1, "new")]));
+        assert_eq!(consumed(&file), (4, 1));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rotated_files_are_finished_first() {
+        let path = scratch_file("rotated");
+        append(&path, "one\n");
+
+        let mut file = FollowedFile::new(&path);
+        assert_eq!(poll(&mut file), lines(&[(1, "one")]));
+
+        // Written before rotation, including a line never terminated
+        append(&path, "two\nthr");
+        fs::rename(&path, path.with_extension("1")).unwrap();
+        assert_eq!(poll(&mut file), lines(&[(2, "two")]));
+
+        append(&path, "first\n");
+        assert_eq!(poll(&mut file), lines(&[(3, "thr"), (1, "first")]));
+        assert_eq!(consumed(&file), (6, 1));
+    }
+
+    #[test]
+    fn resumes_where_an_earlier_run_stopped() {
+        let path = scratch_file("resume");
+        append(&path, "one\n");
+
+        let mut file = FollowedFile::new(&path);
+        assert_eq!(poll(&mut file), lines(&[(1, "one")]));
+        let position = file.position();
+
+        append(&path, "two\n");
+        let mut file = FollowedFile::new(&path).resume_at(position);
+        assert_eq!(poll(&mut file), lines(&[(2, "two")]));
+
+        // Replaced since, so the position belongs to another file
+        fs::write(&path, "uno\ndos\n").unwrap();
+        let mut file = FollowedFile::new(&path).resume_at(position);
+        assert_eq!(poll(&mut file), lines(&[(1, "uno"), (2, "dos")]));
+
+        // Shorter than the position, so truncated since
+        fs::write(&path, "on").unwrap();
+        let mut file = FollowedFile::new(&path).resume_at(position);
+        assert!(poll(&mut file).is_empty());
+        assert_eq!(consumed(&file), (0, 0));
+    }
+}
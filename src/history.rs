@@ -0,0 +1,195 @@
+//! Score history
+//!
+//! Besides the final results, a run can export each team's cumulative score
+//! at the end of every bucket of block heights or of time, for charting
+//! ranks over time and finding when one team overtook another.
+//!
+//! Like the results, the history is derived from every packet seen when it
+//! is read, so it doesn't depend on the order the inputs were scored in.
+//! Each chain has its own heights, so height buckets place every packet on
+//! the reference chain: at the height it was received there, or else sent
+//! from or acknowledged on it. Packets relayed between other chains can't be
+//! placed and are counted apart, so the last bucket plus the unplaced score
+//! is the final result.
+//!
+//! Transaction events carry their block height but not the block time, so
+//! time buckets use the time each packet was first scored rather than when
+//! it was relayed. They are only offered when following the collectors
+//! live, as a backfill scores everything at once.
+
+use crate::report::{self, Format, TeamScore, TieBreaker};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Write};
+use std::str::FromStr;
+
+/// What the history is bucketed by
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BucketBy {
+    /// Height of the reference chain each packet is placed at
+    #[default]
+    Height,
+    /// Unix time in seconds at which each packet was first scored
+    Time,
+}
+
+impl FromStr for BucketBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "height" => Ok(BucketBy::Height),
+            "time" => Ok(BucketBy::Time),
+            _ => Err(format!("unknown bucket {:?}, expected height or time", s)),
+        }
+    }
+}
+
+/// Buckets the score history is recorded in
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct Buckets {
+    /// What the buckets are made of
+    pub by: BucketBy,
+    /// Number of heights or seconds in each bucket
+    pub size: u64,
+}
+
+impl Default for Buckets {
+    fn default() -> Self {
+        Self {
+            by: BucketBy::Height,
+            size: 1000,
+        }
+    }
+}
+
+impl Buckets {
+    /// Last height or second of the bucket `at` falls in
+    pub fn until(self, at: u64) -> u64 {
+        let size = self.size.max(1);
+        (at - at % size).saturating_add(size - 1)
+    }
+}
+
+/// Cumulative score of every team at the end of each bucket, for every
+/// phase
+#[derive(Clone, Debug, Serialize)]
+pub struct History {
+    /// What the buckets are made of
+    pub by: BucketBy,
+    /// Number of heights or seconds in each bucket
+    pub size: u64,
+    /// History of each phase
+    pub phases: Vec<PhaseHistory>,
+}
+
+/// Cumulative scores of one phase
+#[derive(Clone, Debug, Serialize)]
+pub struct PhaseHistory {
+    /// Name used to select the phase
+    pub phase: String,
+    /// Buckets in which a packet was credited, in order. Buckets in which
+    /// nothing was credited are left out.
+    pub buckets: Vec<Bucket>,
+    /// Score of the packets credited to each team which can't be placed in
+    /// any bucket, which only count in the final results
+    pub unplaced: Vec<TeamScore>,
+}
+
+/// Scores at the end of a bucket
+#[derive(Clone, Debug, Serialize)]
+pub struct Bucket {
+    /// Last height or second of the bucket
+    pub until: u64,
+    /// Score every team credited so far had reached by the end of the
+    /// bucket, from the highest total to the lowest
+    pub teams: Vec<TeamScore>,
+}
+
+impl Bucket {
+    /// Rank the teams as in the results
+    pub fn rank(&mut self, tie_breaker: TieBreaker) {
+        report::rank(&mut self.teams, tie_breaker);
+    }
+}
+
+impl History {
+    /// Render the history as JSON or as CSV. Other formats aren't suited to
+    /// a time series and are rendered as JSON.
+    pub fn render(&self, format: Format) -> String {
+        let mut buf = String::new();
+
+        match format {
+            Format::Csv => self.write_csv(&mut buf),
+            Format::Json | Format::Text | Format::Markdown | Format::Html => {
+                buf = serde_json::to_string_pretty(self).expect("history is serializable");
+                buf.push('\n');
+                Ok(())
+            }
+        }
+        .expect("writing to a String can't fail");
+
+        buf
+    }
+
+    /// One row per team at the end of each bucket, followed by rows with an
+    /// empty `until` for the packets which can't be placed in a bucket
+    fn write_csv(&self, f: &mut impl Write) -> fmt::Result {
+        let mut categories: Vec<&str> = Vec::new();
+
+        for phase in self.phases.iter() {
+            let buckets = phase.buckets.iter().map(|bucket| &bucket.teams);
+
+            for teams in buckets.chain(Some(&phase.unplaced)) {
+                for team in teams.iter() {
+                    for (category, _) in team.score.iter() {
+                        if !categories.contains(category) {
+                            categories.push(category);
+                        }
+                    }
+                }
+            }
+        }
+
+        write!(f, "phase,until,rank,team,total,gap,percentile")?;
+
+        for category in categories.iter() {
+            write!(f, ",{}", report::csv_field(category))?;
+        }
+
+        writeln!(f)?;
+
+        for phase in self.phases.iter() {
+            for bucket in phase.buckets.iter() {
+                for team in bucket.teams.iter() {
+                    write!(
+                        f,
+                        "{},{},{},{},{},{},{}",
+                        report::csv_field(&phase.phase),
+                        bucket.until,
+                        team.rank,
+                        report::csv_field(&team.team),
+                        team.total,
+                        report::optional(team.gap),
+                        team.percentile
+                    )?;
+                    report::write_csv_counts(f, &categories, &team.score)?;
+                }
+            }
+
+            for team in phase.unplaced.iter() {
+                write!(
+                    f,
+                    "{},,,{},{},,",
+                    report::csv_field(&phase.phase),
+                    report::csv_field(&team.team),
+                    team.total
+                )?;
+                report::write_csv_counts(f, &categories, &team.score)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,425 @@
+//! Event file inputs
+//!
+//! Inputs are given as files, directories searched recursively for event
+//! files, or glob patterns, optionally through a [manifest](crate::manifest).
+//!
+//! Archived event dumps are usually compressed. Inputs compressed with gzip
+//! or zstd are recognized by their magic bytes, or by a `.gz` or `.zst`
+//! extension, and decompressed as they are read.
+
+use crate::error::{Error, ErrorKind};
+use flate2::bufread::MultiGzDecoder;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Magic bytes at the start of a gzip member
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+/// Magic bytes at the start of a zstd frame
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Number of bytes at the start of an input which identify it
+const HEAD_LEN: usize = 4096;
+
+/// Extensions of the event files picked up in directories, before any
+/// compression extension
+const EVENT_EXTENSIONS: &[&str] = &["json", "jsonl"];
+
+/// An event file to score
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Input {
+    /// Path of the file
+    pub path: PathBuf,
+    /// Chain-id every envelope in the file is expected to come from
+    #[serde(default)]
+    pub chain_id: Option<String>,
+    /// Expected SHA-256 checksum of the file as stored, hex encoded
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+impl Input {
+    /// Input without any hints
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            chain_id: None,
+            sha256: None,
+        }
+    }
+
+    /// Check the file against its expected checksum, if it has one
+    pub fn verify(&self) -> Result<(), Error> {
+        if let Some(expected) = &self.sha256 {
+            let mut hasher = Sha256::new();
+            io::copy(&mut File::open(&self.path)?, &mut hasher)?;
+            let actual = subtle_encoding::hex::encode(hasher.result());
+
+            if !expected.as_bytes().eq_ignore_ascii_case(&actual) {
+                return Err(ErrorKind::Manifest
+                    .context(format!(
+                        "SHA-256 checksum is {}, expected {}",
+                        String::from_utf8_lossy(&actual),
+                        expected
+                    ))
+                    .into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// How far an input has been consumed
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct Position {
+    /// Bytes consumed, after decompression
+    pub offset: u64,
+    /// Lines consumed
+    pub line: u64,
+    /// Digest of the first bytes consumed, telling the file the offset
+    /// belongs to apart from a file which has replaced it since
+    pub head: u64,
+}
+
+/// First bytes read from an input, up to [`HEAD_LEN`]
+#[derive(Clone, Debug, Default)]
+pub struct Head(Vec<u8>);
+
+impl Head {
+    /// Add bytes read after the ones already added
+    pub fn push(&mut self, data: &[u8]) {
+        let room = HEAD_LEN.saturating_sub(self.0.len()).min(data.len());
+        self.0.extend_from_slice(&data[..room]);
+    }
+
+    /// Position after `offset` bytes and `line` lines of the input
+    pub fn position(&self, offset: u64, line: u64) -> Position {
+        Position {
+            offset,
+            line,
+            head: self.digest(offset),
+        }
+    }
+
+    /// Digest of the bytes among the first `offset` ones, or 0 if there are
+    /// none. Only the first 8 bytes of the SHA-256 hash are kept, as it
+    /// only has to tell files apart.
+    fn digest(&self, offset: u64) -> u64 {
+        let len = self.0.len().min(offset as usize);
+
+        if len == 0 {
+            return 0;
+        }
+
+        let mut digest = [0; 8];
+        digest.copy_from_slice(&Sha256::digest(&self.0[..len])[..8]);
+        u64::from_be_bytes(digest)
+    }
+
+    /// Read the bytes among the first `offset` ones of `reader`, checking
+    /// they are the ones `position` was consumed from
+    pub fn read(reader: &mut impl Read, position: Position) -> io::Result<Self> {
+        let len = (HEAD_LEN as u64).min(position.offset);
+        let mut head = Vec::new();
+
+        if reader.take(len).read_to_end(&mut head)? < len as usize {
+            return Err(shorter_than(position.offset));
+        }
+
+        let head = Head(head);
+
+        if head.digest(position.offset) != position.head {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "input has been replaced since its checkpoint",
+            ));
+        }
+
+        Ok(head)
+    }
+}
+
+/// How an input file is compressed
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Compression {
+    /// Plain JSONL
+    None,
+    /// gzip, possibly with several concatenated members
+    Gzip,
+    /// zstd
+    Zstd,
+}
+
+impl Compression {
+    /// Compression of the file at `path`
+    pub fn of(path: &Path) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        Ok(Self::detect(path, reader.fill_buf()?))
+    }
+
+    /// Compression of a file given its path and first bytes. Magic bytes
+    /// win over the extension.
+    fn detect(path: &Path, head: &[u8]) -> Self {
+        if head.starts_with(GZIP_MAGIC) {
+            return Compression::Gzip;
+        }
+
+        if head.starts_with(ZSTD_MAGIC) {
+            return Compression::Zstd;
+        }
+
+        match path.extension().and_then(OsStr::to_str) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+}
+
+/// Open an event file for reading, decompressing it if needed
+pub fn open(path: &Path) -> io::Result<Box<dyn BufRead + Send>> {
+    open_at(path, Position::default()).map(|(reader, _)| reader)
+}
+
+/// Open an event file for reading from `position` into its decompressed
+/// contents, along with its first bytes to keep track of its identity.
+/// Plain files are seeked; compressed files have to be decompressed up to
+/// the offset.
+///
+/// Fails with [`io::ErrorKind::UnexpectedEof`] if the file is shorter than
+/// the offset, and with [`io::ErrorKind::InvalidData`] if its first bytes
+/// aren't the ones the position was consumed from.
+pub fn open_at(path: &Path, position: Position) -> io::Result<(Box<dyn BufRead + Send>, Head)> {
+    let offset = position.offset;
+    let mut reader = BufReader::new(File::open(path)?);
+    let compression = Compression::detect(path, reader.fill_buf()?);
+
+    let mut reader: Box<dyn BufRead + Send> = match compression {
+        Compression::None => {
+            if reader.get_ref().metadata()?.len() < offset {
+                return Err(shorter_than(offset));
+            }
+
+            let head = Head::read(&mut reader, position)?;
+            reader.seek(SeekFrom::Start(offset))?;
+            return Ok((Box::new(reader), head));
+        }
+        Compression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(reader))),
+        Compression::Zstd => Box::new(BufReader::new(zstd::stream::read::Decoder::with_buffer(
+            reader,
+        )?)),
+    };
+
+    let head = Head::read(&mut reader, position)?;
+    let rest = offset - head.0.len() as u64;
+
+    if io::copy(&mut reader.by_ref().take(rest), &mut io::sink())? < rest {
+        return Err(shorter_than(offset));
+    }
+
+    Ok((reader, head))
+}
+
+/// A line of an event file without its line ending
+pub fn trim_line_ending(line: &[u8]) -> &[u8] {
+    let end = line
+        .iter()
+        .rposition(|&byte| byte != b'\r' && byte != b'\n')
+        .map_or(0, |last| last + 1);
+
+    &line[..end]
+}
+
+/// Error for an input which ends before an offset it was read up to
+fn shorter_than(offset: u64) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        format!("input is shorter than its checkpoint offset {}", offset),
+    )
+}
+
+/// Event files named by an input path.
+///
+/// Directories are searched recursively for `.json` and `.jsonl` files,
+/// compressed or not, and paths which don't exist but contain glob
+/// metacharacters are expanded as patterns. Anything else is taken to be a
+/// file, even if it doesn't exist yet.
+pub fn expand(path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut files = Vec::new();
+
+    if path.is_dir() {
+        walk(path, &mut files)?;
+    } else if !path.exists() && is_pattern(path) {
+        let pattern = path.to_string_lossy();
+        let matches = glob::glob(&pattern).map_err(|e| {
+            ErrorKind::Config.context(format!("invalid glob pattern {}: {}", pattern, e))
+        })?;
+
+        for matched in matches {
+            let matched = matched.map_err(|e| ErrorKind::Io.context(e))?;
+
+            if matched.is_dir() {
+                walk(&matched, &mut files)?;
+            } else {
+                files.push(matched);
+            }
+        }
+
+        if files.is_empty() {
+            return Err(ErrorKind::Config
+                .context(format!("{} matches no files", pattern))
+                .into());
+        }
+    } else {
+        files.push(path.to_owned());
+    }
+
+    Ok(files)
+}
+
+/// Add the event files in a directory and its subdirectories, in path order
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+
+    for entry in entries {
+        if entry.is_dir() {
+            walk(&entry, files)?;
+        } else if is_event_file(&entry) {
+            files.push(entry);
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether a file is named like an event file
+fn is_event_file(path: &Path) -> bool {
+    let path = match path.extension().and_then(OsStr::to_str) {
+        Some("gz") | Some("zst") => path.with_extension(""),
+        _ => path.to_owned(),
+    };
+
+    match path.extension().and_then(OsStr::to_str) {
+        Some(extension) => EVENT_EXTENSIONS.contains(&extension),
+        None => false,
+    }
+}
+
+/// Whether a path contains glob metacharacters
+fn is_pattern(path: &Path) -> bool {
+    path.to_string_lossy().contains(&['*', '?', '['][..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression as GzLevel};
+    use std::io::Write;
+    use std::{env, process};
+
+    const EVENTS: &str = "{\"line\":1}\n{\"line\":2}\n{\"line\":3}\n";
+
+    /// Empty directory for the files written by a test
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("goz_scoring_input_{}_{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("temporary directory is writable");
+        dir
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), GzLevel::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// Position after the first `offset` bytes of `EVENTS`
+    fn after(offset: usize) -> Position {
+        let mut head = Head::default();
+        head.push(EVENTS.as_bytes());
+        head.position(offset as u64, 0)
+    }
+
+    fn read_from(path: &Path, position: Position) -> String {
+        let mut contents = String::new();
+        open_at(path, position)
+            .and_then(|(mut reader, _)| reader.read_to_string(&mut contents))
+            .expect("event file is readable");
+        contents
+    }
+
+    #[test]
+    fn compression_is_detected_by_magic_bytes_before_extension() {
+        let dir = scratch_dir("detect");
+        let cases = [
+            ("plain.jsonl", EVENTS.as_bytes().to_vec(), Compression::None),
+            ("gzip.jsonl", gzip(EVENTS.as_bytes()), Compression::Gzip),
+            (
+                "zstd.jsonl",
+                zstd::stream::encode_all(EVENTS.as_bytes(), 0).unwrap(),
+                Compression::Zstd,
+            ),
+            ("empty.jsonl.gz", Vec::new(), Compression::Gzip),
+            ("empty.jsonl.zst", Vec::new(), Compression::Zstd),
+        ];
+
+        for (name, contents, compression) in cases.iter() {
+            let path = dir.join(name);
+            fs::write(&path, contents).unwrap();
+            assert_eq!(Compression::of(&path).unwrap(), *compression, "{}", name);
+        }
+    }
+
+    #[test]
+    fn offsets_are_in_decompressed_bytes() {
+        let dir = scratch_dir("offsets");
+        let (first, rest) = EVENTS.split_at(11);
+
+        // Archives may be concatenated gzip members
+        let mut members = gzip(first.as_bytes());
+        members.extend(gzip(rest.as_bytes()));
+
+        let files = [
+            ("events.jsonl", EVENTS.as_bytes().to_vec()),
+            ("events.jsonl.gz", members),
+            (
+                "events.jsonl.zst",
+                zstd::stream::encode_all(EVENTS.as_bytes(), 0).unwrap(),
+            ),
+        ];
+
+        for (name, contents) in files.iter() {
+            let path = dir.join(name);
+            fs::write(&path, contents).unwrap();
+
+            assert_eq!(read_from(&path, after(0)), EVENTS, "{}", name);
+            assert_eq!(read_from(&path, after(11)), rest, "{}", name);
+            assert_eq!(read_from(&path, after(EVENTS.len())), "", "{}", name);
+
+            let error = open_at(&path, after(EVENTS.len() + 1)).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof, "{}", name);
+        }
+    }
+
+    #[test]
+    fn replaced_inputs_are_refused() {
+        let dir = scratch_dir("replaced");
+        let path = dir.join("events.jsonl");
+        fs::write(&path, EVENTS).unwrap();
+        let position = after(11);
+
+        fs::write(&path, EVENTS.replace("1", "4")).unwrap();
+
+        let error = open_at(&path, position).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
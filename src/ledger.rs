@@ -0,0 +1,71 @@
+//! Audit ledger
+//!
+//! Every scoring decision is appended to a JSONL file so a team's score can
+//! be traced back to the exact transactions that produced it.
+//!
+//! A packet's classification can change once later inputs reveal more about
+//! it, e.g. the Hub transfer that sent it. The corrected decision is appended
+//! as a new entry which records the team and category it replaces.
+
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+/// A single scoring decision
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LedgerEntry {
+    /// Phase which made the decision
+    pub phase: String,
+    /// Hash of the transaction carrying the packets
+    pub tx_hash: String,
+    /// Chain the transaction was committed on
+    pub chain_id: String,
+    /// Position of the packet among the transaction's packet receipts
+    pub packet_index: usize,
+    /// Address the packets were attributed to
+    pub sender: String,
+    /// Team credited with the packets
+    pub team: String,
+    /// How the team was resolved: `sender` or `client_id`
+    pub attributed_by: String,
+    /// Score category credited
+    pub category: String,
+    /// Number of packets credited
+    pub packets: u64,
+    /// Team credited by the decision this entry corrects
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_team: Option<String>,
+    /// Category credited by the decision this entry corrects
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_category: Option<String>,
+}
+
+/// Append-only JSONL audit ledger
+///
+/// A ledger without a file discards every entry.
+#[derive(Debug, Default)]
+pub struct Ledger {
+    file: Option<File>,
+}
+
+impl Ledger {
+    /// Open the ledger at `path`, appending to it if it already exists
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Self { file: Some(file) })
+    }
+
+    /// Append an entry to the ledger
+    pub fn append(&mut self, entry: &LedgerEntry) -> Result<(), Error> {
+        if let Some(file) = self.file.as_mut() {
+            let mut line = serde_json::to_vec(entry).expect("ledger entry is serializable");
+            line.push(b'\n');
+            file.write_all(&line)?;
+        }
+
+        Ok(())
+    }
+}
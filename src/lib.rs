@@ -16,7 +16,19 @@
 )]
 
 pub mod application;
+pub mod checkpoint;
 pub mod commands;
 pub mod config;
 pub mod error;
+pub mod follow;
+pub mod history;
+pub mod input;
+pub mod ledger;
+pub mod manifest;
+pub mod metrics;
+pub mod output;
+pub mod phases;
 pub mod prelude;
+pub mod quarantine;
+pub mod report;
+pub mod rpc;
@@ -0,0 +1,138 @@
+//! Input manifests
+//!
+//! A manifest is a TOML file listing the inputs of a run, each with an
+//! optional chain-id its envelopes must come from and an optional SHA-256
+//! checksum the file must match before it is scored:
+//!
+//! ```toml
+//! [[input]]
+//! path = "dumps/gameofzoneshub-3"
+//! chain_id = "gameofzoneshub-3"
+//!
+//! [[input]]
+//! path = "dumps/archive/*.jsonl.gz"
+//!
+//! [[input]]
+//! path = "dumps/musselnet.jsonl.zst"
+//! sha256 = "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae"
+//! ```
+//!
+//! Relative paths are relative to the manifest. Like the inputs given on the
+//! command line they may be directories or glob patterns, in which case the
+//! chain-id applies to every file they name. A checksum can only be given
+//! for an entry naming a single file.
+
+use crate::error::{Error, ErrorKind};
+use crate::input::{self, Input};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Inputs listed in a manifest file
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// Listed inputs, in order
+    #[serde(default, rename = "input")]
+    pub inputs: Vec<Input>,
+}
+
+impl Manifest {
+    /// Load the manifest at `path`, resolving its relative paths
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let mut manifest: Manifest = toml::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| ErrorKind::Manifest.context(format!("{}: {}", path.display(), e)))?;
+
+        if let Some(dir) = path.parent() {
+            for input in manifest.inputs.iter_mut() {
+                input.path = dir.join(&input.path);
+            }
+        }
+
+        Ok(manifest)
+    }
+}
+
+/// Event files named by the command-line inputs followed by the manifest's,
+/// with directories and glob patterns expanded.
+///
+/// A file named more than once is only scored once, keeping the hints of
+/// every entry naming it. An entry with a checksum which names several files
+/// is refused, as no file could match it.
+pub fn resolve(paths: &[PathBuf], manifest: &Manifest) -> Result<Vec<Input>, Error> {
+    let entries = paths
+        .iter()
+        .map(|path| Input::new(path.clone()))
+        .chain(manifest.inputs.iter().cloned());
+
+    let mut inputs: Vec<Input> = Vec::new();
+
+    for entry in entries {
+        let paths = input::expand(&entry.path)?;
+
+        if entry.sha256.is_some() && paths.len() > 1 {
+            return Err(ErrorKind::Manifest
+                .context(format!(
+                    "{} names {} files but has a single checksum",
+                    entry.path.display(),
+                    paths.len()
+                ))
+                .into());
+        }
+
+        for path in paths {
+            match inputs.iter_mut().find(|input| input.path == path) {
+                Some(input) => {
+                    input.chain_id = input.chain_id.take().or_else(|| entry.chain_id.clone());
+                    input.sha256 = input.sha256.take().or_else(|| entry.sha256.clone());
+                }
+                None => inputs.push(Input {
+                    path,
+                    ..entry.clone()
+                }),
+            }
+        }
+    }
+
+    Ok(inputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    #[test]
+    fn checksums_only_apply_to_single_files() {
+        let dir = env::temp_dir().join(format!("goz_scoring_manifest_{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("temporary directory is writable");
+
+        for name in ["a.jsonl", "b.jsonl"].iter() {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        let manifest = |path: PathBuf| Manifest {
+            inputs: vec![Input {
+                sha256: Some("00".to_owned()),
+                ..Input::new(path)
+            }],
+        };
+
+        assert_eq!(
+            resolve(&[], &manifest(dir.join("a.jsonl"))).unwrap().len(),
+            1
+        );
+        assert!(resolve(&[], &manifest(dir.clone())).is_err());
+        assert!(resolve(&[], &manifest(dir.join("*.jsonl"))).is_err());
+    }
+
+    #[test]
+    fn unknown_fields_are_refused() {
+        let misspelt = "[[input]]\npath = \"events.jsonl\"\nsha265 = \"00\"\n";
+        assert!(toml::from_str::<Manifest>(misspelt).is_err());
+
+        let misnamed = "[[inputs]]\npath = \"events.jsonl\"\n";
+        assert!(toml::from_str::<Manifest>(misnamed).is_err());
+    }
+}
@@ -0,0 +1,264 @@
+//! Prometheus metrics
+//!
+//! While scoring runs continuously, `serve` and `start --metrics` expose
+//! the state of the run in the Prometheus text format at `/metrics`:
+//!
+//! - `goz_scoring_team_score{phase,team}`: weighted total score
+//! - `goz_scoring_team_packets{phase,team,category}`: count in each scoring
+//!   category
+//! - `goz_scoring_envelopes_total{chain_id}`: envelopes scored
+//! - `goz_scoring_last_envelope_timestamp_seconds{chain_id}`: Unix time the
+//!   last envelope was scored
+//! - `goz_scoring_parse_failures_total`: input lines or posted envelopes
+//!   which couldn't be parsed
+//! - `goz_scoring_duplicate_packets_total{phase,chain_id}`: packets which
+//!   were already scored
+//! - `goz_scoring_unscored_events{phase,chain_id,reason}`: events which
+//!   were not scored, by reason
+//!
+//! Packets are classified again as later events are seen, so a later event
+//! can move a packet to another category or team. Team scores and unscored events are
+//! therefore gauges rather than counters.
+//!
+//! Collectors don't send the block time, so the last envelope timestamp is
+//! the time it was scored. Alerting on it catches a collector which stopped
+//! delivering.
+
+use crate::error::{Error, ErrorKind};
+use crate::phases::SkipReason;
+use crate::prelude::*;
+use crate::report::Report;
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tiny_http::{Header, Response, Server};
+
+/// Envelopes scored and parse failures, which the phases don't keep
+#[derive(Clone, Debug, Default)]
+pub struct Activity {
+    /// Envelopes scored from each chain-id
+    envelopes: BTreeMap<String, u64>,
+    /// Unix time the last envelope from each chain-id was scored
+    last_envelope_at: BTreeMap<String, u64>,
+    /// Input lines or posted envelopes which couldn't be parsed
+    parse_failures: u64,
+}
+
+impl Activity {
+    /// Count an envelope from `chain_id` scored now
+    pub fn envelope(&mut self, chain_id: &str) {
+        *self.envelopes.entry(chain_id.to_owned()).or_default() += 1;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        self.last_envelope_at.insert(chain_id.to_owned(), now);
+    }
+
+    /// Count an input which couldn't be parsed
+    pub fn parse_failure(&mut self) {
+        self.parse_failures += 1;
+    }
+
+    /// Add the activity of a shard of the inputs
+    pub fn merge(&mut self, shard: Activity) {
+        for (chain_id, count) in shard.envelopes {
+            *self.envelopes.entry(chain_id).or_default() += count;
+        }
+
+        for (chain_id, at) in shard.last_envelope_at {
+            let last = self.last_envelope_at.entry(chain_id).or_default();
+            *last = (*last).max(at);
+        }
+
+        self.parse_failures += shard.parse_failures;
+    }
+}
+
+/// Render the metrics of a run in the Prometheus text format
+pub fn render(report: &Report, activity: &Activity) -> String {
+    let mut buf = String::new();
+    write_metrics(&mut buf, report, activity).expect("writing to a String can't fail");
+    buf
+}
+
+fn write_metrics(f: &mut impl Write, report: &Report, activity: &Activity) -> fmt::Result {
+    header(
+        f,
+        "goz_scoring_team_score",
+        "gauge",
+        "Weighted total score of each team",
+    )?;
+
+    for phase in report.phases.iter() {
+        for team in phase.teams.iter() {
+            writeln!(
+                f,
+                "goz_scoring_team_score{{phase=\"{}\",team=\"{}\"}} {}",
+                label(&phase.phase),
+                label(&team.team),
+                team.total
+            )?;
+        }
+    }
+
+    header(
+        f,
+        "goz_scoring_team_packets",
+        "gauge",
+        "Packets credited to each team in each scoring category",
+    )?;
+
+    for phase in report.phases.iter() {
+        for team in phase.teams.iter() {
+            for (category, count) in team.score.iter() {
+                writeln!(
+                    f,
+                    "goz_scoring_team_packets{{phase=\"{}\",team=\"{}\",category=\"{}\"}} {}",
+                    label(&phase.phase),
+                    label(&team.team),
+                    label(category),
+                    count
+                )?;
+            }
+        }
+    }
+
+    header(
+        f,
+        "goz_scoring_envelopes_total",
+        "counter",
+        "Envelopes scored from each chain",
+    )?;
+
+    for (chain_id, count) in activity.envelopes.iter() {
+        writeln!(
+            f,
+            "goz_scoring_envelopes_total{{chain_id=\"{}\"}} {}",
+            label(chain_id),
+            count
+        )?;
+    }
+
+    header(
+        f,
+        "goz_scoring_last_envelope_timestamp_seconds",
+        "gauge",
+        "Unix time the last envelope from each chain was scored",
+    )?;
+
+    for (chain_id, at) in activity.last_envelope_at.iter() {
+        writeln!(
+            f,
+            "goz_scoring_last_envelope_timestamp_seconds{{chain_id=\"{}\"}} {}",
+            label(chain_id),
+            at
+        )?;
+    }
+
+    header(
+        f,
+        "goz_scoring_parse_failures_total",
+        "counter",
+        "Input lines or posted envelopes which couldn't be parsed",
+    )?;
+    writeln!(
+        f,
+        "goz_scoring_parse_failures_total {}",
+        activity.parse_failures
+    )?;
+
+    header(
+        f,
+        "goz_scoring_duplicate_packets_total",
+        "counter",
+        "Packets which were already scored",
+    )?;
+
+    for phase in report.phases.iter() {
+        for (chain_id, reasons) in phase.skipped.by_chain.iter() {
+            if let Some(count) = reasons.get(&SkipReason::Duplicate) {
+                writeln!(
+                    f,
+                    "goz_scoring_duplicate_packets_total{{phase=\"{}\",chain_id=\"{}\"}} {}",
+                    label(&phase.phase),
+                    label(chain_id),
+                    count
+                )?;
+            }
+        }
+    }
+
+    header(
+        f,
+        "goz_scoring_unscored_events",
+        "gauge",
+        "Events which were not scored, by reason",
+    )?;
+
+    for phase in report.phases.iter() {
+        for (chain_id, reasons) in phase.skipped.by_chain.iter() {
+            for (reason, count) in reasons.iter() {
+                writeln!(
+                    f,
+                    "goz_scoring_unscored_events{{phase=\"{}\",chain_id=\"{}\",reason=\"{}\"}} {}",
+                    label(&phase.phase),
+                    label(chain_id),
+                    reason,
+                    count
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Write the `HELP` and `TYPE` lines of a metric
+fn header(f: &mut impl Write, name: &str, kind: &str, help: &str) -> fmt::Result {
+    writeln!(f, "# HELP {} {}", name, help)?;
+    writeln!(f, "# TYPE {} {}", name, kind)
+}
+
+/// Escape a label value
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// `Content-Type` of the Prometheus text format
+pub fn content_type() -> Header {
+    Header::from_bytes(
+        &b"Content-Type"[..],
+        &b"text/plain; version=0.0.4; charset=utf-8"[..],
+    )
+    .expect("valid header")
+}
+
+/// Serve the application's metrics at `/metrics` on `listen` from a
+/// background thread
+pub fn serve(listen: &str) -> Result<(), Error> {
+    let server = Server::http(listen)
+        .map_err(|e| ErrorKind::Io.context(format!("could not listen on {}: {}", listen, e)))?;
+
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = match request.url() {
+                "/metrics" => {
+                    Response::from_string(app_reader().metrics()).with_header(content_type())
+                }
+                _ => Response::from_string("not found").with_status_code(404),
+            };
+
+            if let Err(e) = request.respond(response) {
+                status_warn!("could not send response: {}", e);
+            }
+        }
+    });
+
+    Ok(())
+}
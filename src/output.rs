@@ -0,0 +1,141 @@
+//! Results files
+//!
+//! Results are written to a temporary file next to the destination and then
+//! renamed over it, so readers never see a partially written report. The
+//! destination is checked before any input is scored, so a run can't score
+//! for hours only to find it has nowhere to put the results.
+
+use crate::error::{Error, ErrorKind};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What to do when the results file already exists
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Policy {
+    /// Fail before scoring anything
+    #[default]
+    Refuse,
+    /// Replace the existing file
+    Overwrite,
+    /// Add the time the run started to the file name, e.g.
+    /// `results-20201018T093000Z.txt`
+    Timestamp,
+}
+
+/// Destination of the results
+#[derive(Clone, Debug)]
+pub struct Output {
+    path: PathBuf,
+}
+
+impl Output {
+    /// Destination writing to `path` according to `policy`, after checking
+    /// the results can be written there
+    pub fn open(path: &Path, policy: Policy) -> Result<Self, Error> {
+        let path = match policy {
+            Policy::Timestamp => timestamped(path, SystemTime::now()),
+            Policy::Refuse | Policy::Overwrite => path.to_owned(),
+        };
+
+        if path.is_dir() {
+            return Err(ErrorKind::Io
+                .context(format!("{} is a directory", path.display()))
+                .into());
+        }
+
+        if policy != Policy::Overwrite && path.exists() {
+            return Err(ErrorKind::Io
+                .context(format!(
+                    "{} already exists, pass --overwrite to replace it or --timestamp to keep it",
+                    path.display()
+                ))
+                .into());
+        }
+
+        let output = Self { path };
+
+        // Creating the temporary file is what writing the results needs
+        let tmp_path = output.tmp_path();
+        File::create(&tmp_path)?;
+        fs::remove_file(&tmp_path)?;
+
+        Ok(output)
+    }
+
+    /// Path the results are written to
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Replace the results with `contents`
+    pub fn write(&self, contents: &str) -> Result<(), Error> {
+        let tmp_path = self.tmp_path();
+
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        Ok(())
+    }
+
+    fn tmp_path(&self) -> PathBuf {
+        let mut tmp_path = self.path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        tmp_path.into()
+    }
+}
+
+/// `path` with the UTC time added to its file name, before the extension
+fn timestamped(path: &Path, time: SystemTime) -> PathBuf {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+
+    let mut name = OsString::new();
+    name.push(path.file_stem().unwrap_or_default());
+    name.push(format!("-{}", utc_timestamp(seconds)));
+
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+
+    path.with_file_name(name)
+}
+
+/// Basic ISO 8601 UTC timestamp of a Unix time, e.g. `20201018T093000Z`
+pub(crate) fn utc_timestamp(seconds: u64) -> String {
+    // Civil date from days since the epoch, after Howard Hinnant's
+    // `civil_from_days`
+    let days = seconds / 86_400;
+    let era = (days + 719_468) / 146_097;
+    let day_of_era = days + 719_468 - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    let time = seconds % 86_400;
+
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        time / 3_600,
+        time % 3_600 / 60,
+        time % 60
+    )
+}
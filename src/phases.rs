@@ -0,0 +1,100 @@
+//! GozScoring Phases
+//!
+//! Each phase of Game of Zones was scored with its own rules. A phase
+//! consumes sagan envelopes one at a time and produces a score for every
+//! team along with a report.
+//!
+//! Phases are selected by name with the `phases` config setting or the
+//! `start --phase` flag:
+//!
+//! - `phase2`: opaque packets relayed to and from the Hub
+
+mod attribution;
+mod clients;
+mod lifecycle;
+mod phase2;
+mod skips;
+
+pub use self::attribution::RECV_PACKET_ACTIONS;
+pub use self::lifecycle::{Completion, PacketId, PacketLifecycle};
+pub use self::phase2::{Category, PacketKey, Phase2OpaquePackets, Score};
+pub use self::skips::{SkipCounts, SkipReason};
+
+use crate::config::GozScoringConfig;
+use crate::error::Error;
+use crate::history::{Buckets, PhaseHistory};
+use crate::ledger::Ledger;
+use crate::report::PhaseReport;
+use sagan::message::Envelope;
+use serde::Serialize;
+use std::any::Any;
+use std::fmt;
+
+/// A team's place on a phase's leaderboard
+#[derive(Clone, Debug, Serialize)]
+pub struct Standing {
+    /// Rank of the team, shared by tied teams
+    pub rank: u64,
+    /// Name of the team
+    pub team: String,
+    /// Total score of the team
+    pub score: f64,
+    /// Points behind the team ranked just above, none for the first team
+    pub gap: Option<f64>,
+    /// Percentage of the other teams ranked below this one
+    pub percentile: f64,
+}
+
+/// Scoring rules for one phase of the competition
+pub trait ScoringPhase: fmt::Debug + Send + Sync {
+    /// Name used to select this phase
+    fn name(&self) -> &'static str;
+
+    /// Record every event in the envelope. Events may be scored in any
+    /// order; packets are classified again as the events they depend on are
+    /// seen.
+    fn score_envelope(&mut self, envelope: &Envelope);
+
+    /// Append every scoring decision made or changed since the last flush
+    /// to the audit ledger. Decisions which couldn't be written are tried
+    /// again by the next flush.
+    fn flush_ledger(&mut self, ledger: &mut Ledger) -> Result<(), Error>;
+
+    /// Empty phase with the same configuration, used to score a shard of
+    /// the inputs on another thread
+    fn fork(&self) -> Box<dyn ScoringPhase>;
+
+    /// Add the events of a shard forked from this phase. Shards must be
+    /// merged in input order; the result is the same as if the shard's
+    /// events had been scored by this phase.
+    fn merge(&mut self, shard: Box<dyn ScoringPhase>);
+
+    /// Convert into `Any` so a shard can be downcast to its concrete type
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+
+    /// Serialize everything scored so far, for a checkpoint
+    fn save(&self) -> serde_json::Value;
+
+    /// Replace the scoring state with one saved in a checkpoint
+    fn restore(&mut self, state: serde_json::Value) -> Result<(), serde_json::Error>;
+
+    /// Score of every team in each scoring category, followed by a summary
+    /// of the events which were not scored
+    fn report(&self) -> PhaseReport;
+
+    /// Unranked cumulative score of every team at the end of each bucket in
+    /// which a packet was credited, and the score of the packets which
+    /// can't be placed in any bucket
+    fn history(&self, buckets: Buckets) -> PhaseHistory;
+}
+
+/// Names of every phase this binary can score
+pub const PHASE_NAMES: &[&str] = &[Phase2OpaquePackets::NAME];
+
+/// Build the phase with the given name, if there is one
+pub fn build(name: &str, config: &GozScoringConfig) -> Option<Box<dyn ScoringPhase>> {
+    match name {
+        Phase2OpaquePackets::NAME => Some(Box::new(Phase2OpaquePackets::new(config))),
+        _ => None,
+    }
+}
@@ -0,0 +1,348 @@
+//! Attribution of received packets to the relayer which delivered them
+//!
+//! Tendermint flattens the events of every message in a transaction into a
+//! single map, so `message.action`, `message.sender` and the `recv_packet.*`
+//! attributes are separate lists. Each message emits exactly one
+//! `message.action`, which is used to find the position of every packet
+//! receipt among the transaction's messages and pair it with the signer of
+//! that message.
+//!
+//! Modules moving coins while a message is handled emit `message.sender`
+//! too. A transfer received through IBC adds the transfer module account
+//! after the relayer's senders, so there are more senders than messages.
+//! Such transactions are attributed to the only relayer among the senders,
+//! leaving out module accounts and, failing that, any address which isn't a
+//! team's.
+
+use super::SkipReason;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+use subtle_encoding::bech32::decode;
+
+/// `message.action` values of messages which deliver a packet
+pub const RECV_PACKET_ACTIONS: &[&str] = &["ics04/opaque", "recv_packet"];
+
+/// Modules whose accounts move coins while a relayer's messages are handled
+const MODULE_ACCOUNTS: &[&str] = &[
+    "transfer",
+    "fee_collector",
+    "distribution",
+    "bonded_tokens_pool",
+    "not_bonded_tokens_pool",
+    "gov",
+    "mint",
+];
+
+/// A packet received in a transaction, paired with the relayer which
+/// delivered it
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RelayedPacket<'a> {
+    /// Position of the packet among the transaction's packet receipts
+    pub index: usize,
+    /// Signer of the message carrying the packet
+    pub sender: &'a str,
+    /// Port the packet was sent on
+    pub src_port: Option<&'a str>,
+    /// Channel the packet was sent on
+    pub src_channel: &'a str,
+    /// Sequence number of the packet on its channel
+    pub sequence: Option<&'a str>,
+    /// Port the packet was received on
+    pub dst_port: Option<&'a str>,
+    /// Channel the packet was received on
+    pub dst_channel: Option<&'a str>,
+}
+
+/// Pair every `recv_packet` in the event data with the signer of the
+/// message which carried it. `is_team` tells whether an address is
+/// registered to a team.
+pub fn relayed_packets<'a>(
+    data: &'a HashMap<String, Vec<String>>,
+    is_team: impl Fn(&str) -> bool,
+) -> Result<Vec<RelayedPacket<'a>>, SkipReason> {
+    let senders = data
+        .get("message.sender")
+        .filter(|senders| !senders.is_empty())
+        .ok_or(SkipReason::MissingSender)?;
+
+    let src_channels = data
+        .get("recv_packet.packet_src_channel")
+        .filter(|src_channels| !src_channels.is_empty())
+        .ok_or(SkipReason::MissingSrcChannel)?;
+
+    let attribute =
+        |name: &str| -> &[String] { data.get(name).map(Vec::as_slice).unwrap_or_default() };
+
+    let src_ports = attribute("recv_packet.packet_src_port");
+    let sequences = attribute("recv_packet.packet_sequence");
+    let dst_ports = attribute("recv_packet.packet_dst_port");
+    let dst_channels = attribute("recv_packet.packet_dst_channel");
+
+    let actions = attribute("message.action");
+
+    let signers = message_signers(senders, actions.len());
+
+    let recv_messages: Vec<usize> = actions
+        .iter()
+        .enumerate()
+        .filter(|(_, action)| RECV_PACKET_ACTIONS.contains(&action.as_str()))
+        .map(|(position, _)| position)
+        .collect();
+
+    let packet_senders: Vec<&str> = match signers {
+        Some(signers) if recv_messages.len() == src_channels.len() => recv_messages
+            .iter()
+            .map(|&position| signers[position])
+            .collect(),
+        _ => {
+            // Without a message for every packet, the packets can only be
+            // attributed if a single relayer signed the whole transaction
+            let sender = sole_signer(data, is_team).ok_or(SkipReason::AmbiguousSender)?;
+            vec![sender; src_channels.len()]
+        }
+    };
+
+    Ok(packet_senders
+        .into_iter()
+        .zip(src_channels.iter())
+        .enumerate()
+        .map(|(index, (sender, src_channel))| RelayedPacket {
+            index,
+            sender,
+            src_port: src_ports.get(index).map(String::as_str),
+            src_channel,
+            sequence: sequences.get(index).map(String::as_str),
+            dst_port: dst_ports.get(index).map(String::as_str),
+            dst_channel: dst_channels.get(index).map(String::as_str),
+        })
+        .collect())
+}
+
+/// The relayer which signed every message, if a single address in
+/// `message.sender` isn't a module account, or failing that if a single one
+/// is registered to a team
+pub fn sole_signer(
+    data: &HashMap<String, Vec<String>>,
+    is_team: impl Fn(&str) -> bool,
+) -> Option<&str> {
+    let senders: BTreeSet<&str> = data
+        .get("message.sender")?
+        .iter()
+        .map(String::as_str)
+        .collect();
+
+    only(
+        senders
+            .iter()
+            .copied()
+            .filter(|sender| !is_module_account(sender)),
+    )
+    .or_else(|| only(senders.iter().copied().filter(|sender| is_team(sender))))
+}
+
+/// The only address yielded by `addresses`
+fn only<'a>(mut addresses: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    match (addresses.next(), addresses.next()) {
+        (Some(address), None) => Some(address),
+        _ => None,
+    }
+}
+
+/// Whether `address` is the account of one of the [`MODULE_ACCOUNTS`] on
+/// any chain
+fn is_module_account(address: &str) -> bool {
+    match decode(address) {
+        Ok((_, data)) => MODULE_ACCOUNTS
+            .iter()
+            .any(|module| Sha256::digest(module.as_bytes())[..20] == data[..]),
+        Err(_) => false,
+    }
+}
+
+/// Signer of each of the transaction's `message_count` messages, if they can
+/// be told apart
+///
+/// Signers are only paired with messages by position when every message
+/// emitted exactly one `message.sender`. A message emitting the signer more
+/// than once, or another address, leaves no way to tell which sender belongs
+/// to which message.
+fn message_signers(senders: &[String], message_count: usize) -> Option<Vec<&str>> {
+    if message_count == 0 || senders.len() != message_count {
+        return None;
+    }
+
+    Some(senders.iter().map(String::as_str).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Flattened events of a transaction, as Tendermint reports them
+    fn tx(attributes: &[(&str, &[&str])]) -> HashMap<String, Vec<String>> {
+        attributes
+            .iter()
+            .map(|(key, values)| {
+                (
+                    (*key).to_owned(),
+                    values.iter().map(|value| (*value).to_owned()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    /// Account of the transfer module, which pays out received transfers
+    const TRANSFER_MODULE: &str = "cosmos1yl6hdjhmkf37639730gffanpzndzdpmhwlkfhr";
+
+    fn no_teams(_: &str) -> bool {
+        false
+    }
+
+    fn senders<'a>(packets: &[RelayedPacket<'a>]) -> Vec<&'a str> {
+        packets.iter().map(|packet| packet.sender).collect()
+    }
+
+    #[test]
+    fn single_signer() {
+        let data = tx(&[
+            ("message.action", &["update_client", "ics04/opaque"]),
+            ("message.sender", &["cosmos1relayer", "cosmos1relayer"]),
+            ("recv_packet.packet_src_port", &["transfer"]),
+            ("recv_packet.packet_src_channel", &["hubchannel"]),
+            ("recv_packet.packet_dst_port", &["transfer"]),
+            ("recv_packet.packet_dst_channel", &["zonechannel"]),
+            ("recv_packet.packet_sequence", &["7"]),
+        ]);
+
+        let packets = relayed_packets(&data, no_teams).unwrap();
+
+        assert_eq!(
+            packets,
+            vec![RelayedPacket {
+                index: 0,
+                sender: "cosmos1relayer",
+                src_port: Some("transfer"),
+                src_channel: "hubchannel",
+                sequence: Some("7"),
+                dst_port: Some("transfer"),
+                dst_channel: Some("zonechannel"),
+            }]
+        );
+    }
+
+    #[test]
+    fn multi_signer() {
+        let data = tx(&[
+            (
+                "message.action",
+                &[
+                    "update_client",
+                    "ics04/opaque",
+                    "update_client",
+                    "ics04/opaque",
+                ],
+            ),
+            (
+                "message.sender",
+                &["cosmos1alice", "cosmos1alice", "cosmos1bob", "cosmos1bob"],
+            ),
+            (
+                "recv_packet.packet_src_channel",
+                &["hubchannel", "hubchannel"],
+            ),
+            ("recv_packet.packet_sequence", &["1", "2"]),
+        ]);
+
+        let packets = relayed_packets(&data, no_teams).unwrap();
+
+        assert_eq!(senders(&packets), vec!["cosmos1alice", "cosmos1bob"]);
+        assert_eq!(packets[1].sequence, Some("2"));
+    }
+
+    #[test]
+    fn extra_sender_from_the_only_signer() {
+        let data = tx(&[
+            ("message.action", &["update_client", "ics04/opaque"]),
+            (
+                "message.sender",
+                &["cosmos1relayer", "cosmos1relayer", "cosmos1relayer"],
+            ),
+            ("recv_packet.packet_src_channel", &["hubchannel"]),
+        ]);
+
+        let packets = relayed_packets(&data, no_teams).unwrap();
+
+        assert_eq!(senders(&packets), vec!["cosmos1relayer"]);
+    }
+
+    #[test]
+    fn extra_sender_is_not_paired_by_position() {
+        // Either message could have emitted the extra sender
+        let data = tx(&[
+            ("message.action", &["ics04/opaque", "ics04/opaque"]),
+            (
+                "message.sender",
+                &["cosmos1alice", "cosmos1alice", "cosmos1bob"],
+            ),
+            (
+                "recv_packet.packet_src_channel",
+                &["hubchannel", "hubchannel"],
+            ),
+        ]);
+
+        assert_eq!(
+            relayed_packets(&data, no_teams),
+            Err(SkipReason::AmbiguousSender)
+        );
+    }
+
+    #[test]
+    fn transfer_relayed_with_a_module_sender() {
+        // A transfer received on a zone: the relayer signs the client update
+        // and the receipt, and the transfer module account pays out the
+        // vouchers after them
+        let data = tx(&[
+            ("message.action", &["update_client", "ics04/opaque"]),
+            ("message.module", &["ibc_client", "ibc_channel"]),
+            (
+                "message.sender",
+                &[
+                    "cosmos1relayer",
+                    "cosmos1relayer",
+                    "cosmos1relayer",
+                    TRANSFER_MODULE,
+                ],
+            ),
+            ("recv_packet.packet_src_port", &["transfer"]),
+            ("recv_packet.packet_src_channel", &["hubchannel"]),
+            ("recv_packet.packet_dst_port", &["transfer"]),
+            ("recv_packet.packet_dst_channel", &["zonechannel"]),
+            ("recv_packet.packet_sequence", &["12"]),
+            ("transfer.recipient", &["cosmos1receiver"]),
+        ]);
+
+        let packets = relayed_packets(&data, no_teams).unwrap();
+
+        assert_eq!(senders(&packets), vec!["cosmos1relayer"]);
+    }
+
+    #[test]
+    fn extra_sender_outside_the_teams() {
+        let data = tx(&[
+            ("message.action", &["update_client", "ics04/opaque"]),
+            (
+                "message.sender",
+                &["cosmos1alice", "cosmos1alice", "cosmos1stranger"],
+            ),
+            ("recv_packet.packet_src_channel", &["hubchannel"]),
+        ]);
+
+        assert_eq!(
+            relayed_packets(&data, no_teams),
+            Err(SkipReason::AmbiguousSender)
+        );
+
+        let packets = relayed_packets(&data, |sender| sender == "cosmos1alice").unwrap();
+        assert_eq!(senders(&packets), vec!["cosmos1alice"]);
+    }
+}
@@ -0,0 +1,122 @@
+//! Light client graph built from channel and connection handshakes
+//!
+//! Every team registered the light client it relays through. Following a
+//! packet's destination channel to its connection and from there to its
+//! client identifies the team whose client received the packet.
+
+use relayer_modules::events::IBCEvent;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Channels and connections observed on each chain
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ClientGraph {
+    /// Connection of each channel, by chain-id
+    channels: BTreeMap<String, BTreeMap<String, String>>,
+    /// Client of each connection, by chain-id
+    connections: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl ClientGraph {
+    /// Record the channel or connection opened by a handshake event, and
+    /// return the channels on `chain_id` whose client may have changed
+    pub fn observe(&mut self, chain_id: &str, event: &IBCEvent) -> Vec<String> {
+        match event {
+            IBCEvent::OpenInitChannel(ref inner_event) => self.add_channel(
+                chain_id,
+                inner_event.channel_id.to_string(),
+                inner_event.connection_id.to_string(),
+            ),
+            IBCEvent::OpenTryChannel(ref inner_event) => self.add_channel(
+                chain_id,
+                inner_event.channel_id.to_string(),
+                inner_event.connection_id.to_string(),
+            ),
+            IBCEvent::OpenInitConnection(ref inner_event) => self.add_connection(
+                chain_id,
+                inner_event.connection_id.to_string(),
+                inner_event.client_id.to_string(),
+            ),
+            IBCEvent::OpenTryConnection(ref inner_event) => self.add_connection(
+                chain_id,
+                inner_event.connection_id.to_string(),
+                inner_event.client_id.to_string(),
+            ),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Client backing `channel_id` on `chain_id`, if its handshakes were seen
+    pub fn client_of(&self, chain_id: &str, channel_id: &str) -> Option<&str> {
+        let connection_id = self.channels.get(chain_id)?.get(channel_id)?;
+
+        self.connections
+            .get(chain_id)?
+            .get(connection_id)
+            .map(String::as_str)
+    }
+
+    /// Add the handshakes observed by a shard of the inputs which follow
+    /// the ones observed so far. Later handshakes replace earlier ones, as if
+    /// they had been observed in order. Returns the chains and channels whose
+    /// client may have changed.
+    pub fn merge(&mut self, shard: ClientGraph) -> Vec<(String, String)> {
+        let mut changed = Vec::new();
+
+        for (chain_id, channels) in shard.channels {
+            for (channel_id, connection_id) in channels {
+                for channel_id in self.add_channel(&chain_id, channel_id, connection_id) {
+                    changed.push((chain_id.clone(), channel_id));
+                }
+            }
+        }
+
+        for (chain_id, connections) in shard.connections {
+            for (connection_id, client_id) in connections {
+                for channel_id in self.add_connection(&chain_id, connection_id, client_id) {
+                    changed.push((chain_id.clone(), channel_id));
+                }
+            }
+        }
+
+        changed
+    }
+
+    fn add_channel(
+        &mut self,
+        chain_id: &str,
+        channel_id: String,
+        connection_id: String,
+    ) -> Vec<String> {
+        self.channels
+            .entry(chain_id.to_owned())
+            .or_default()
+            .insert(channel_id.clone(), connection_id);
+
+        vec![channel_id]
+    }
+
+    /// Record a connection, returning the channels opened on top of it
+    fn add_connection(
+        &mut self,
+        chain_id: &str,
+        connection_id: String,
+        client_id: String,
+    ) -> Vec<String> {
+        let channels = self
+            .channels
+            .get(chain_id)
+            .into_iter()
+            .flatten()
+            .filter(|(_, connection)| **connection == connection_id)
+            .map(|(channel_id, _)| channel_id.clone())
+            .collect();
+
+        self.connections
+            .entry(chain_id.to_owned())
+            .or_default()
+            .insert(connection_id, client_id);
+
+        channels
+    }
+}
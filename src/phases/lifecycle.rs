@@ -0,0 +1,456 @@
+//! Packet lifecycle tracking
+//!
+//! A packet is sent on one chain, received on the counterparty chain, and
+//! then either acknowledged or timed out back on the sending chain.
+//!
+//! Ports, channels and sequence numbers are only unique on a single chain,
+//! so a packet is identified by the chain it was sent from along with the
+//! port, channel and sequence it was sent with, and its receipt by the chain
+//! it was received on along with the port and channel it was received on.
+//! Receipts are matched with the packet they belong to when they are read,
+//! so events can be observed in any order.
+
+use relayer_modules::events::IBCEvent;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+/// Identifies a packet by one end of its channel: the chain it was sent from
+/// or received on, the port and channel on that chain, and its sequence
+/// number
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Deserialize, Serialize)]
+pub struct PacketId {
+    /// Port the packet was sent from or received on
+    pub port: String,
+    /// Channel the packet was sent from or received on
+    pub channel: String,
+    /// Sequence number of the packet on its channel
+    pub sequence: String,
+    /// Chain the packet was sent from or received on. Ordered last so that
+    /// packets sent with the same port, channel and sequence by different
+    /// chains are adjacent.
+    pub chain_id: String,
+}
+
+impl PacketId {
+    /// Identify a packet by the chain, port and channel at one end of its
+    /// channel and its sequence number
+    pub fn new(
+        chain_id: &str,
+        port: &impl fmt::Display,
+        channel: &impl fmt::Display,
+        sequence: &impl fmt::Display,
+    ) -> Self {
+        Self {
+            port: port.to_string(),
+            channel: channel.to_string(),
+            sequence: sequence.to_string(),
+            chain_id: chain_id.to_owned(),
+        }
+    }
+}
+
+/// How a packet's lifecycle ended
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Completion {
+    /// The acknowledgement was relayed back to the sending chain
+    Acknowledged,
+    /// The timeout was relayed back to the sending chain
+    TimedOut,
+}
+
+/// Everything observed on the sending chain about a single packet
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct PacketLifecycle {
+    /// Whether the packet's send was seen
+    pub sent: bool,
+    /// Height of the block the packet was sent in, if known
+    pub sent_at: Option<u64>,
+    /// Port the packet was sent to, if its events carry it
+    pub dst_port: Option<String>,
+    /// Channel the packet was sent to, if its events carry it
+    pub dst_channel: Option<String>,
+    /// How the packet's lifecycle ended
+    pub completion: Option<Completion>,
+    /// Team which relayed the acknowledgement or timeout
+    pub completed_by: Option<String>,
+    /// Height of the block the acknowledgement or timeout was relayed in,
+    /// if known
+    pub completed_at: Option<u64>,
+}
+
+impl PacketLifecycle {
+    /// Whether the packet may have been received at the given receiving end
+    fn sent_to(&self, receipt: &PacketId) -> bool {
+        self.dst_port.iter().all(|port| *port == receipt.port)
+            && self
+                .dst_channel
+                .iter()
+                .all(|channel| *channel == receipt.channel)
+    }
+}
+
+/// A packet received on a chain
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Receipt {
+    /// Port the packet was sent from
+    pub src_port: String,
+    /// Channel the packet was sent from
+    pub src_channel: String,
+}
+
+/// Lifecycle of every packet seen so far
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct PacketTracker {
+    /// Packets by the chain, port and channel they were sent from
+    #[serde(with = "crate::checkpoint::entries")]
+    packets: BTreeMap<PacketId, PacketLifecycle>,
+    /// Receipts by the chain, port and channel they were received on
+    #[serde(with = "crate::checkpoint::entries")]
+    receipts: BTreeMap<PacketId, Receipt>,
+}
+
+/// Packets whose lifecycle an observation may have changed
+#[derive(Debug, Default)]
+pub struct Touched {
+    /// Packets by the chain, port and channel they were sent from
+    pub sent: Vec<PacketId>,
+    /// Receipts by the chain, port and channel they were received on
+    pub received: Vec<PacketId>,
+}
+
+/// Ports, channels and sequence number carried by a packet event, with the
+/// height of its block
+struct PacketEvent {
+    src_port: String,
+    src_channel: String,
+    dst_port: Option<String>,
+    dst_channel: Option<String>,
+    sequence: String,
+    height: Option<u64>,
+}
+
+/// Step of a packet's lifecycle recorded by an event
+enum Step {
+    Sent,
+    Received,
+    Completed(Completion),
+}
+
+impl PacketEvent {
+    /// Id of the packet on the chain it was sent from
+    fn sent_id(&self, chain_id: &str) -> PacketId {
+        PacketId::new(chain_id, &self.src_port, &self.src_channel, &self.sequence)
+    }
+}
+
+impl PacketTracker {
+    /// Record a typed packet event
+    pub fn observe(&mut self, chain_id: &str, event: &IBCEvent) -> Touched {
+        let (packet, step) = match event {
+            IBCEvent::SendPacketChannel(ref packet) => (packet, Step::Sent),
+            IBCEvent::ReceivePacketChannel(ref packet) => (packet, Step::Received),
+            IBCEvent::AcknowledgePacketChannel(ref packet) => {
+                (packet, Step::Completed(Completion::Acknowledged))
+            }
+            IBCEvent::TimeoutPacketChannel(ref packet) => {
+                (packet, Step::Completed(Completion::TimedOut))
+            }
+            _ => return Touched::default(),
+        };
+
+        let packet = PacketEvent {
+            src_port: packet.packet_src_port.to_string(),
+            src_channel: packet.packet_src_channel.to_string(),
+            dst_port: Some(packet.packet_dst_port.to_string()),
+            dst_channel: Some(packet.packet_dst_channel.to_string()),
+            sequence: packet.packet_sequence.to_string(),
+            height: packet.height.to_string().parse().ok(),
+        };
+
+        let mut touched = Touched::default();
+
+        match step {
+            Step::Sent => self.sent(chain_id, &packet, &mut touched),
+            Step::Received => self.received(chain_id, &packet, &mut touched),
+            Step::Completed(completion) => {
+                self.completed(chain_id, &packet, completion, None, &mut touched)
+            }
+        }
+
+        touched
+    }
+
+    /// Record every packet found in the flattened events of a transaction.
+    ///
+    /// `relayer_team` is the team which signed the transaction, if known, and
+    /// is credited with relaying any acknowledgements or timeouts in it.
+    pub fn observe_data(
+        &mut self,
+        chain_id: &str,
+        data: &HashMap<String, Vec<String>>,
+        relayer_team: Option<&str>,
+    ) -> Touched {
+        let mut touched = Touched::default();
+
+        for packet in packet_events(data, "send_packet") {
+            self.sent(chain_id, &packet, &mut touched);
+        }
+
+        for packet in packet_events(data, "recv_packet") {
+            self.received(chain_id, &packet, &mut touched);
+        }
+
+        for packet in packet_events(data, "acknowledge_packet") {
+            let completion = Completion::Acknowledged;
+            self.completed(chain_id, &packet, completion, relayer_team, &mut touched);
+        }
+
+        for packet in packet_events(data, "timeout_packet") {
+            let completion = Completion::TimedOut;
+            self.completed(chain_id, &packet, completion, relayer_team, &mut touched);
+        }
+
+        touched
+    }
+
+    /// Port, channel and sequence the packet received with the given id was
+    /// sent with, under an empty chain-id
+    pub fn source_of(&self, receipt: &PacketId) -> Option<PacketId> {
+        let source = self.receipts.get(receipt)?;
+        Some(PacketId::new(
+            "",
+            &source.src_port,
+            &source.src_channel,
+            &receipt.sequence,
+        ))
+    }
+
+    /// The packet received with the given id, if exactly one chain other
+    /// than the receiving one is known to have sent it there
+    pub fn sender_of(&self, receipt: &PacketId) -> Option<(&PacketId, &PacketLifecycle)> {
+        let first = self.source_of(receipt)?;
+
+        let mut senders = self
+            .packets
+            .range(&first..)
+            .take_while(|(id, _)| {
+                id.port == first.port
+                    && id.channel == first.channel
+                    && id.sequence == first.sequence
+            })
+            .filter(|(id, packet)| id.chain_id != receipt.chain_id && packet.sent_to(receipt));
+
+        match (senders.next(), senders.next()) {
+            (Some(sender), None) => Some(sender),
+            _ => None,
+        }
+    }
+
+    /// Add the packets observed by a shard of the inputs which follow the
+    /// ones observed so far. What was observed first wins, as if the shard
+    /// had been observed in order.
+    pub fn merge(&mut self, shard: PacketTracker) -> Touched {
+        let mut touched = Touched::default();
+
+        for (id, observed) in shard.packets {
+            touched.sent.push(id.clone());
+            let packet = self.packets.entry(id).or_default();
+
+            packet.sent |= observed.sent;
+
+            if packet.sent_at.is_none() {
+                packet.sent_at = observed.sent_at;
+            }
+
+            if packet.dst_port.is_none() {
+                packet.dst_port = observed.dst_port;
+            }
+
+            if packet.dst_channel.is_none() {
+                packet.dst_channel = observed.dst_channel;
+            }
+
+            if packet.completion.is_none() {
+                packet.completion = observed.completion;
+            }
+
+            if packet.completed_by.is_none() {
+                packet.completed_by = observed.completed_by;
+            }
+
+            if packet.completed_at.is_none() {
+                packet.completed_at = observed.completed_at;
+            }
+        }
+
+        for (id, receipt) in shard.receipts {
+            touched.received.push(id.clone());
+            self.receipts.entry(id).or_insert(receipt);
+        }
+
+        touched
+    }
+
+    /// Write packet totals followed by the packets still in flight on each
+    /// channel
+    pub fn summarize(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        let count = |filter: &dyn Fn(&PacketLifecycle) -> bool| {
+            self.packets
+                .values()
+                .filter(|packet| filter(packet))
+                .count()
+        };
+
+        writeln!(
+            f,
+            "Packets sent: {}, received: {}, acknowledged: {}, timed out: {}",
+            count(&|packet| packet.sent),
+            self.receipts.len(),
+            count(&|packet| packet.completion == Some(Completion::Acknowledged)),
+            count(&|packet| packet.completion == Some(Completion::TimedOut)),
+        )?;
+
+        let delivered: BTreeSet<&PacketId> = self
+            .receipts
+            .keys()
+            .filter_map(|receipt| self.sender_of(receipt))
+            .map(|(id, _)| id)
+            .collect();
+
+        // Packets which were never received, or received but never
+        // acknowledged, for each chain, source port and channel
+        let mut in_flight: BTreeMap<(&str, &str, &str), (u64, u64)> = BTreeMap::new();
+
+        for (id, packet) in self.packets.iter() {
+            if !packet.sent || packet.completion.is_some() {
+                continue;
+            }
+
+            let stuck = in_flight
+                .entry((id.chain_id.as_str(), id.port.as_str(), id.channel.as_str()))
+                .or_default();
+
+            if delivered.contains(id) {
+                stuck.1 += 1;
+            } else {
+                stuck.0 += 1;
+            }
+        }
+
+        for ((chain_id, src_port, src_channel), (undelivered, unacknowledged)) in in_flight {
+            writeln!(
+                f,
+                "  in flight on {} {}/{}: {} undelivered, {} unacknowledged",
+                chain_id, src_port, src_channel, undelivered, unacknowledged
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn sent(&mut self, chain_id: &str, packet: &PacketEvent, touched: &mut Touched) {
+        let lifecycle = self.lifecycle(chain_id, packet, touched);
+        lifecycle.sent = true;
+
+        if lifecycle.sent_at.is_none() {
+            lifecycle.sent_at = packet.height;
+        }
+    }
+
+    /// Record a receipt, if its event carries the port and channel it was
+    /// received on
+    fn received(&mut self, chain_id: &str, packet: &PacketEvent, touched: &mut Touched) {
+        if let (Some(dst_port), Some(dst_channel)) = (&packet.dst_port, &packet.dst_channel) {
+            let id = PacketId::new(chain_id, dst_port, dst_channel, &packet.sequence);
+            touched.received.push(id.clone());
+
+            self.receipts.entry(id).or_insert_with(|| Receipt {
+                src_port: packet.src_port.clone(),
+                src_channel: packet.src_channel.clone(),
+            });
+        }
+    }
+
+    /// Record an acknowledgement or timeout. Typed events don't carry the
+    /// relayer, so the team is filled in by whichever event does.
+    fn completed(
+        &mut self,
+        chain_id: &str,
+        packet: &PacketEvent,
+        completion: Completion,
+        relayer_team: Option<&str>,
+        touched: &mut Touched,
+    ) {
+        let lifecycle = self.lifecycle(chain_id, packet, touched);
+
+        if lifecycle.completion.is_none() {
+            lifecycle.completion = Some(completion);
+        }
+
+        if lifecycle.completed_by.is_none() {
+            lifecycle.completed_by = relayer_team.map(str::to_owned);
+        }
+
+        if lifecycle.completed_at.is_none() {
+            lifecycle.completed_at = packet.height;
+        }
+    }
+
+    /// Lifecycle of a packet sent from `chain_id`, noting where it was sent
+    /// to if that wasn't known yet
+    fn lifecycle(
+        &mut self,
+        chain_id: &str,
+        packet: &PacketEvent,
+        touched: &mut Touched,
+    ) -> &mut PacketLifecycle {
+        let id = packet.sent_id(chain_id);
+        touched.sent.push(id.clone());
+
+        let lifecycle = self.packets.entry(id).or_default();
+
+        if lifecycle.dst_port.is_none() {
+            lifecycle.dst_port = packet.dst_port.clone();
+        }
+
+        if lifecycle.dst_channel.is_none() {
+            lifecycle.dst_channel = packet.dst_channel.clone();
+        }
+
+        lifecycle
+    }
+}
+
+/// Packets found under the `{prefix}.packet_*` attributes of a transaction's
+/// flattened events
+fn packet_events(data: &HashMap<String, Vec<String>>, prefix: &str) -> Vec<PacketEvent> {
+    let attribute = |name: &str| {
+        data.get(&format!("{}.{}", prefix, name))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    };
+
+    let dst_ports = attribute("packet_dst_port");
+    let dst_channels = attribute("packet_dst_channel");
+    let height = data
+        .get("tx.height")
+        .and_then(|heights| heights.first())
+        .and_then(|height| height.parse().ok());
+
+    attribute("packet_src_port")
+        .iter()
+        .zip(attribute("packet_src_channel").iter())
+        .zip(attribute("packet_sequence").iter())
+        .enumerate()
+        .map(|(index, ((src_port, src_channel), sequence))| PacketEvent {
+            src_port: src_port.clone(),
+            src_channel: src_channel.clone(),
+            dst_port: dst_ports.get(index).cloned(),
+            dst_channel: dst_channels.get(index).cloned(),
+            sequence: sequence.clone(),
+            height,
+        })
+        .collect()
+}
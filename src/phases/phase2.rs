@@ -0,0 +1,1173 @@
+//! Phase 2: opaque packets relayed to and from the Hub
+
+use super::attribution::{relayed_packets, sole_signer};
+use super::clients::ClientGraph;
+use super::lifecycle::{Completion, PacketId, PacketLifecycle, PacketTracker, Touched};
+use super::{ScoringPhase, SkipCounts, SkipReason};
+use crate::config::{GozScoringConfig, PhaseWindow, ScoringConfig};
+use crate::error::Error;
+use crate::history::{Bucket, BucketBy, Buckets, PhaseHistory};
+use crate::ledger::{Ledger, LedgerEntry};
+use crate::prelude::*;
+use crate::report::{CounterpartyScore, PhaseReport, TeamScore};
+use relayer_modules::events::IBCEvent;
+use sagan::message::Envelope;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+use std::mem;
+use std::time::{SystemTime, UNIX_EPOCH};
+use subtle_encoding::bech32::{decode, encode};
+
+/// Phase 2 score for a single team
+#[derive(Clone, Default, Deserialize, Serialize, Debug)]
+pub struct Score {
+    /// Packets received on the Hub
+    pub hub_opaque_packets: u64,
+    /// Packets received on a zone from any channel but the Hub's
+    pub opaque_packets_tx: u64,
+    /// Packets received on a zone from a channel the Hub sent transfers on
+    pub packets_from_hub: u64,
+    /// Every packet credited
+    pub opaque_packets_total: u64,
+    /// Packets whose acknowledgement the team also relayed back
+    pub round_trips: u64,
+}
+
+impl Score {
+    /// Weighted total of all the scoring categories
+    pub fn total(&self, weights: &ScoringConfig) -> f64 {
+        (self.hub_opaque_packets as f64 * weights.hub_opaque_packets)
+            + (self.packets_from_hub as f64 * weights.packets_from_hub)
+            + (self.opaque_packets_tx as f64 * weights.opaque_packets_tx)
+            + (self.opaque_packets_total as f64 * weights.opaque_packets_total.unwrap_or(0.0))
+            + (self.round_trips as f64 * weights.round_trips)
+    }
+
+    /// Count a packet credited in `category`
+    fn count(&mut self, category: Category, round_trip: bool) {
+        match category {
+            Category::HubOpaquePackets => self.hub_opaque_packets += 1,
+            Category::PacketsFromHub => self.packets_from_hub += 1,
+            Category::OpaquePacketsTx => self.opaque_packets_tx += 1,
+        }
+
+        self.opaque_packets_total += 1;
+
+        if round_trip {
+            self.round_trips += 1;
+        }
+    }
+
+    /// Take back a packet counted with [`Score::count`]
+    fn uncount(&mut self, category: Category, round_trip: bool) {
+        match category {
+            Category::HubOpaquePackets => self.hub_opaque_packets -= 1,
+            Category::PacketsFromHub => self.packets_from_hub -= 1,
+            Category::OpaquePacketsTx => self.opaque_packets_tx -= 1,
+        }
+
+        self.opaque_packets_total -= 1;
+
+        if round_trip {
+            self.round_trips -= 1;
+        }
+    }
+
+    /// Add the counts of another score
+    fn add(&mut self, other: &Score) {
+        self.hub_opaque_packets += other.hub_opaque_packets;
+        self.opaque_packets_tx += other.opaque_packets_tx;
+        self.packets_from_hub += other.packets_from_hub;
+        self.opaque_packets_total += other.opaque_packets_total;
+        self.round_trips += other.round_trips;
+    }
+
+    /// Count in each scoring category, named like the fields
+    pub fn categories(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("hub_opaque_packets", self.hub_opaque_packets),
+            ("opaque_packets_tx", self.opaque_packets_tx),
+            ("packets_from_hub", self.packets_from_hub),
+            ("opaque_packets_total", self.opaque_packets_total),
+            ("round_trips", self.round_trips),
+        ]
+    }
+}
+
+/// Counterparty of packets whose sending chain couldn't be determined
+pub const UNKNOWN_COUNTERPARTY: &str = "unknown";
+
+/// Identifies a received packet by its position inside a transaction
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Deserialize, Serialize)]
+pub struct PacketKey {
+    /// Chain the transaction was committed on
+    pub chain_id: String,
+    /// Hash of the transaction
+    pub tx_hash: String,
+    /// Position of the packet among the transaction's packet receipts
+    pub index: usize,
+}
+
+/// Score category a received packet is credited to
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Category {
+    /// Received on the Hub
+    HubOpaquePackets,
+    /// Received on a zone from a channel the Hub sent transfers on
+    PacketsFromHub,
+    /// Received on a zone from any other channel
+    OpaquePacketsTx,
+}
+
+impl Category {
+    /// Name of the category as used in the scoring config
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Category::HubOpaquePackets => "hub_opaque_packets",
+            Category::PacketsFromHub => "packets_from_hub",
+            Category::OpaquePacketsTx => "opaque_packets_tx",
+        }
+    }
+}
+
+/// How the team credited with a packet was resolved
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Attribution {
+    /// The packet's sender is a registered team address
+    Sender,
+    /// The client which received the packet is registered to a team
+    ClientId,
+}
+
+impl Attribution {
+    fn as_str(self) -> &'static str {
+        match self {
+            Attribution::Sender => "sender",
+            Attribution::ClientId => "client_id",
+        }
+    }
+}
+
+/// A received packet waiting to be classified
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Credit {
+    /// Address the packet was attributed to
+    sender: String,
+    /// Channel the packet was sent on
+    src_channel: String,
+    /// Channel the packet was received on
+    dst_channel: Option<String>,
+    /// Chain, port and channel the packet was received on with its
+    /// sequence, if its events carry them
+    packet: Option<PacketId>,
+    /// Height of the block the packet was received in, if known
+    height: Option<u64>,
+    /// Unix time in seconds at which the packet was first scored
+    scored_at: Option<u64>,
+}
+
+/// How a received packet is scored
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+struct Decision {
+    /// Team credited with the packet
+    team: String,
+    /// How the team was resolved
+    attributed_by: Attribution,
+    /// Category the packet is credited to
+    category: Category,
+    /// Team whose client received the packet, if it isn't `team`
+    mismatched_client: Option<String>,
+}
+
+/// Everything scored so far, as saved in a checkpoint
+#[derive(Deserialize, Serialize)]
+struct Snapshot {
+    #[serde(with = "crate::checkpoint::entries")]
+    credits: BTreeMap<PacketKey, Credit>,
+    #[serde(with = "crate::checkpoint::entries")]
+    ledgered: BTreeMap<PacketKey, Decision>,
+    clients: ClientGraph,
+    source_channels: BTreeSet<String>,
+    packets: PacketTracker,
+    skipped: SkipCounts,
+}
+
+/// How a received packet is classified with everything seen so far
+#[derive(Clone, Debug)]
+struct Classified {
+    /// How the packet is scored, or why it can't be
+    decision: Result<Decision, SkipReason>,
+    /// Whether the team credited also relayed the acknowledgement back
+    round_trip: bool,
+    /// Chain the packet was sent from, if its send was seen
+    sent_on: Option<String>,
+    /// Chain the packet is counted as coming from
+    counterparty: String,
+    /// Height of the reference chain the packet is placed at, if any
+    height: Option<u64>,
+}
+
+/// Scores, client mismatches and skips of every classified packet
+#[derive(Debug, Default)]
+struct Tally {
+    scores: BTreeMap<String, Score>,
+    /// Score of each team broken down by the chain its packets came from
+    by_counterparty: BTreeMap<String, BTreeMap<String, Score>>,
+    /// Number of packets credited to each team at each block height of the
+    /// reference chain
+    heights: BTreeMap<String, BTreeMap<u64, u64>>,
+    /// Number of packets credited to each team which can't be placed on
+    /// the reference chain
+    unplaced: BTreeMap<String, u64>,
+    client_mismatches: BTreeMap<String, BTreeMap<String, u64>>,
+    /// Received packets which can't be credited
+    skipped: SkipCounts,
+}
+
+impl Tally {
+    /// Count a packet received on `chain_id`
+    fn add(&mut self, chain_id: &str, classified: &Classified) {
+        let decision = match &classified.decision {
+            Ok(decision) => decision,
+            Err(reason) => {
+                self.skipped.record(chain_id, *reason);
+                return;
+            }
+        };
+
+        let team = &decision.team;
+
+        self.scores
+            .entry(team.clone())
+            .or_default()
+            .count(decision.category, classified.round_trip);
+
+        self.by_counterparty
+            .entry(team.clone())
+            .or_default()
+            .entry(classified.counterparty.clone())
+            .or_default()
+            .count(decision.category, classified.round_trip);
+
+        match classified.height {
+            Some(height) => {
+                *self
+                    .heights
+                    .entry(team.clone())
+                    .or_default()
+                    .entry(height)
+                    .or_default() += 1;
+            }
+            None => *self.unplaced.entry(team.clone()).or_default() += 1,
+        }
+
+        if let Some(client_team) = &decision.mismatched_client {
+            *self
+                .client_mismatches
+                .entry(team.clone())
+                .or_default()
+                .entry(client_team.clone())
+                .or_default() += 1;
+        }
+    }
+
+    /// Take back a packet counted with [`Tally::add`], leaving nothing
+    /// behind which counting from scratch wouldn't have
+    fn remove(&mut self, chain_id: &str, classified: &Classified) {
+        let decision = match &classified.decision {
+            Ok(decision) => decision,
+            Err(reason) => {
+                self.skipped.forget(chain_id, *reason);
+                return;
+            }
+        };
+
+        let team = &decision.team;
+
+        if let Some(score) = self.scores.get_mut(team) {
+            score.uncount(decision.category, classified.round_trip);
+
+            if score.opaque_packets_total == 0 {
+                self.scores.remove(team);
+            }
+        }
+
+        if let Some(counterparties) = self.by_counterparty.get_mut(team) {
+            if let Some(score) = counterparties.get_mut(&classified.counterparty) {
+                score.uncount(decision.category, classified.round_trip);
+
+                if score.opaque_packets_total == 0 {
+                    counterparties.remove(&classified.counterparty);
+                }
+            }
+
+            if counterparties.is_empty() {
+                self.by_counterparty.remove(team);
+            }
+        }
+
+        match classified.height {
+            Some(height) => take_one(&mut self.heights, team, &height),
+            None => {
+                if let Some(count) = self.unplaced.get_mut(team) {
+                    *count -= 1;
+
+                    if *count == 0 {
+                        self.unplaced.remove(team);
+                    }
+                }
+            }
+        }
+
+        if let Some(client_team) = &decision.mismatched_client {
+            take_one(&mut self.client_mismatches, team, client_team);
+        }
+    }
+
+    /// Highest block height of the reference chain at which a packet
+    /// credited to `team` is placed, unless some of them can't be placed
+    fn reached_at(&self, team: &str) -> Option<u64> {
+        if self.unplaced.contains_key(team) {
+            return None;
+        }
+
+        self.heights
+            .get(team)
+            .and_then(|heights| heights.keys().next_back())
+            .copied()
+    }
+}
+
+/// Take one from the count under `outer` and `inner`, leaving no zero count
+/// or empty map behind
+fn take_one<K: Ord, L: Ord>(counts: &mut BTreeMap<K, BTreeMap<L, u64>>, outer: &K, inner: &L) {
+    if let Some(inner_counts) = counts.get_mut(outer) {
+        if let Some(count) = inner_counts.get_mut(inner) {
+            *count -= 1;
+
+            if *count == 0 {
+                inner_counts.remove(inner);
+            }
+        }
+
+        if inner_counts.is_empty() {
+            counts.remove(outer);
+        }
+    }
+}
+
+/// Chain, port and channel a packet was received on
+type Route = (String, String, String);
+
+/// Classification of every received packet, kept up to date as the events
+/// it depends on are seen so only the packets they affect are classified
+/// again
+#[derive(Debug, Default)]
+struct Classifications {
+    /// How each received packet is classified
+    classified: BTreeMap<PacketKey, Classified>,
+    /// Totals of the classified packets
+    tally: Tally,
+    /// Number of packets received on each chain, port and channel from each
+    /// chain, learned from the packets whose send was seen
+    routes: BTreeMap<Route, BTreeMap<String, u64>>,
+    /// Received packets to classify again
+    stale: BTreeSet<PacketKey>,
+    /// Received packets whose decision may have changed since the audit
+    /// ledger was last written
+    unflushed: BTreeSet<PacketKey>,
+    /// Received packets by the channel they were sent on
+    by_src_channel: BTreeMap<String, BTreeSet<PacketKey>>,
+    /// Received packets by the chain and channel they were received on
+    by_dst_channel: BTreeMap<(String, String), BTreeSet<PacketKey>>,
+    /// Received packets by their receiving end
+    by_receipt: BTreeMap<PacketId, BTreeSet<PacketKey>>,
+    /// Received packets by the port, channel and sequence they were sent
+    /// with, under an empty chain-id
+    by_source: BTreeMap<PacketId, BTreeSet<PacketKey>>,
+}
+
+impl Classifications {
+    /// Index a new received packet and classify it on the next refresh
+    fn insert(&mut self, key: &PacketKey, credit: &Credit) {
+        self.by_src_channel
+            .entry(credit.src_channel.clone())
+            .or_default()
+            .insert(key.clone());
+
+        if let Some(dst_channel) = &credit.dst_channel {
+            self.by_dst_channel
+                .entry((key.chain_id.clone(), dst_channel.clone()))
+                .or_default()
+                .insert(key.clone());
+        }
+
+        if let Some(id) = &credit.packet {
+            self.by_receipt
+                .entry(id.clone())
+                .or_default()
+                .insert(key.clone());
+        }
+
+        self.stale.insert(key.clone());
+    }
+
+    /// Classify again the packets sent on a Hub source channel
+    fn src_channel_changed(&mut self, channel: &str) {
+        if let Some(keys) = self.by_src_channel.get(channel) {
+            self.stale.extend(keys.iter().cloned());
+        }
+    }
+
+    /// Classify again the packets received on a channel whose client or
+    /// route changed
+    fn dst_channel_changed(&mut self, chain_id: &str, channel: &str) {
+        if let Some(keys) = self
+            .by_dst_channel
+            .get(&(chain_id.to_owned(), channel.to_owned()))
+        {
+            self.stale.extend(keys.iter().cloned());
+        }
+    }
+
+    /// Classify again the packets whose send, receipt, acknowledgement or
+    /// timeout was observed
+    fn packets_changed(&mut self, touched: &Touched) {
+        for id in touched.received.iter() {
+            if let Some(keys) = self.by_receipt.get(id) {
+                self.stale.extend(keys.iter().cloned());
+            }
+        }
+
+        for id in touched.sent.iter() {
+            let source = PacketId::new("", &id.port, &id.channel, &id.sequence);
+
+            if let Some(keys) = self.by_source.get(&source) {
+                self.stale.extend(keys.iter().cloned());
+            }
+        }
+    }
+
+    /// Chain packets received on `route` come from, if they all came from
+    /// the same one
+    fn route(&self, route: &Route) -> Option<&str> {
+        let senders = self.routes.get(route)?;
+
+        if senders.len() == 1 {
+            senders.keys().next().map(String::as_str)
+        } else {
+            None
+        }
+    }
+}
+
+/// Scores opaque packets, weighting packets received on the Hub and
+/// packets sent from the Hub above all other packets
+///
+/// Whether a packet came from the Hub is only known once the Hub transfer
+/// sending it has been seen, which may be in a later input. Received packets
+/// are therefore classified again whenever an event they depend on is seen,
+/// so every ordering of the inputs produces the same results.
+#[derive(Debug)]
+pub struct Phase2OpaquePackets {
+    /// Chain-ids of the Hub
+    hub_id: Vec<String>,
+
+    /// Chain whose block heights are compared across teams
+    reference_chain: Option<String>,
+
+    /// Scoring weights
+    weights: ScoringConfig,
+
+    /// Heights outside of which opaque packets are not scored
+    window: PhaseWindow,
+
+    /// Every received packet
+    credits: BTreeMap<PacketKey, Credit>,
+
+    /// How every received packet is classified so far
+    index: Classifications,
+
+    /// Decisions already written to the audit ledger
+    ledgered: BTreeMap<PacketKey, Decision>,
+
+    /// Hashmap from Address to team
+    address_to_team: HashMap<String, String>,
+
+    /// Hashmap from the client-id each team registered on the Hub to team
+    client_to_team: HashMap<String, String>,
+
+    /// Channels and connections opened on each chain
+    clients: ClientGraph,
+
+    ///Source channels on the Hub
+    source_channels: BTreeSet<String>,
+
+    /// Lifecycle of every packet sent, received, acknowledged or timed out
+    packets: PacketTracker,
+
+    /// Opaque packets which were not scored
+    skipped: SkipCounts,
+}
+
+impl Phase2OpaquePackets {
+    /// Name used to select this phase
+    pub const NAME: &'static str = "phase2";
+
+    /// Create a new phase scorer from the application config
+    pub fn new(config: &GozScoringConfig) -> Self {
+        Self {
+            hub_id: config.hub_id.clone(),
+            reference_chain: config.reference_chain().map(str::to_owned),
+            weights: config.scoring.clone(),
+            window: config.windows.get(Self::NAME).cloned().unwrap_or_default(),
+            credits: BTreeMap::new(),
+            index: Classifications::default(),
+            ledgered: BTreeMap::new(),
+            address_to_team: config.build_hashmaps(),
+            client_to_team: config.build_client_hashmap(),
+            clients: ClientGraph::default(),
+            source_channels: BTreeSet::new(),
+            packets: PacketTracker::default(),
+            skipped: SkipCounts::default(),
+        }
+    }
+
+    /// Collect every packet relayed by an opaque packet transaction, or
+    /// return the reason it can't be credited
+    fn score_opaque_packet(
+        &mut self,
+        chain_id: &str,
+        data: &HashMap<String, Vec<String>>,
+    ) -> Result<(), SkipReason> {
+        let hash = data
+            .get("tx.hash")
+            .and_then(|tx_hashes| tx_hashes.first())
+            .ok_or(SkipReason::MissingTxHash)?;
+
+        let is_team = |address: &str| self.get_team_by_address(address).is_some();
+
+        for packet in relayed_packets(data, is_team)? {
+            // Don't double count packets
+            let key = PacketKey {
+                chain_id: chain_id.to_owned(),
+                tx_hash: hash.clone(),
+                index: packet.index,
+            };
+
+            if self.credits.contains_key(&key) {
+                self.skipped.record(chain_id, SkipReason::Duplicate);
+                continue;
+            }
+
+            let id = match (packet.dst_port, packet.dst_channel, packet.sequence) {
+                (Some(dst_port), Some(dst_channel), Some(sequence)) => {
+                    Some(PacketId::new(chain_id, &dst_port, &dst_channel, &sequence))
+                }
+                _ => None,
+            };
+
+            let credit = Credit {
+                sender: packet.sender.to_owned(),
+                src_channel: packet.src_channel.to_owned(),
+                dst_channel: packet.dst_channel.map(str::to_owned),
+                packet: id,
+                height: tx_height(data),
+                scored_at: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .ok()
+                    .map(|duration| duration.as_secs()),
+            };
+
+            self.index.insert(&key, &credit);
+            self.credits.insert(key, credit);
+        }
+
+        Ok(())
+    }
+
+    /// Classify a received packet using everything seen so far
+    fn decide(&self, key: &PacketKey, credit: &Credit) -> Result<Decision, SkipReason> {
+        // Each relayer is credited only for the packets it delivered
+        // itself. Packets from unknown relayers fall back to the team
+        // whose client received them. Teams registered their client on the
+        // Hub, and a zone's client may have the same id.
+        let sender_team = self.get_team_by_address(&credit.sender).cloned();
+        let client_team = credit
+            .dst_channel
+            .as_ref()
+            .filter(|_| self.hub_id.contains(&key.chain_id))
+            .and_then(|channel| self.clients.client_of(&key.chain_id, channel))
+            .and_then(|client_id| self.client_to_team.get(client_id))
+            .cloned();
+
+        let (team, attributed_by, mismatched_client) = match (sender_team, client_team) {
+            (Some(sender_team), Some(client_team)) if sender_team != client_team => {
+                (sender_team, Attribution::Sender, Some(client_team))
+            }
+            (Some(sender_team), _) => (sender_team, Attribution::Sender, None),
+            (None, Some(client_team)) => (client_team, Attribution::ClientId, None),
+            (None, None) => return Err(SkipReason::UnknownTeam),
+        };
+
+        let category = if self.hub_id.contains(&key.chain_id) {
+            Category::HubOpaquePackets
+        } else if self.source_channels.contains(&credit.src_channel) {
+            Category::PacketsFromHub
+        } else {
+            Category::OpaquePacketsTx
+        };
+
+        Ok(Decision {
+            team,
+            attributed_by,
+            category,
+            mismatched_client,
+        })
+    }
+
+    /// Track the lifecycle of every packet in a transaction's events
+    fn track_packets(&mut self, chain_id: &str, data: &HashMap<String, Vec<String>>) {
+        let relayer_team = sole_signer(data, |address| self.get_team_by_address(address).is_some())
+            .and_then(|sender| self.get_team_by_address(sender))
+            .cloned();
+
+        let touched = self
+            .packets
+            .observe_data(chain_id, data, relayer_team.as_deref());
+        self.index.packets_changed(&touched);
+    }
+
+    /// Id and lifecycle of a received packet on the chain it was sent from,
+    /// if it can be told which chain that is
+    fn sender_of(&self, credit: &Credit) -> Option<(&PacketId, &PacketLifecycle)> {
+        credit
+            .packet
+            .as_ref()
+            .and_then(|id| self.packets.sender_of(id))
+    }
+
+    /// Height of the reference chain a received packet is placed at: the
+    /// height it was received at on the reference chain, or else the height
+    /// it was sent or acknowledged at there. Packets relayed between other
+    /// chains can't be placed.
+    fn placed_height(
+        &self,
+        key: &PacketKey,
+        credit: &Credit,
+        sender: Option<(&PacketId, &PacketLifecycle)>,
+    ) -> Option<u64> {
+        let reference_chain = self.reference_chain.as_deref()?;
+
+        if key.chain_id == reference_chain {
+            return credit.height;
+        }
+
+        match sender {
+            Some((id, packet)) if id.chain_id == reference_chain => {
+                packet.sent_at.or(packet.completed_at)
+            }
+            _ => None,
+        }
+    }
+
+    /// Classify again every received packet an event seen since the last
+    /// refresh may have changed the classification of
+    fn refresh(&mut self) {
+        let mut index = mem::take(&mut self.index);
+
+        while !index.stale.is_empty() {
+            for key in mem::take(&mut index.stale) {
+                if let Some(credit) = self.credits.get(&key) {
+                    self.classify(&mut index, key, credit);
+                }
+            }
+        }
+
+        self.index = index;
+    }
+
+    /// Classify a received packet using everything seen so far, replacing
+    /// its previous classification
+    fn classify(&self, index: &mut Classifications, key: PacketKey, credit: &Credit) {
+        let route = credit
+            .packet
+            .as_ref()
+            .map(|id| (id.chain_id.clone(), id.port.clone(), id.channel.clone()));
+        let route_before = route
+            .as_ref()
+            .and_then(|route| index.route(route))
+            .map(str::to_owned);
+
+        if let Some(previous) = index.classified.remove(&key) {
+            index.tally.remove(&key.chain_id, &previous);
+
+            if let (Some(route), Some(sent_on)) = (&route, &previous.sent_on) {
+                take_one(&mut index.routes, route, sent_on);
+            }
+        }
+
+        let decision = self.decide(&key, credit);
+        let sender = self.sender_of(credit);
+        let sent_on = sender.map(|(id, _)| id.chain_id.clone());
+
+        // Packets are credited once their send is seen, so a packet whose
+        // send is seen later is found by the port, channel and sequence it
+        // was sent with
+        if let Some(source) = credit
+            .packet
+            .as_ref()
+            .and_then(|id| self.packets.source_of(id))
+        {
+            index
+                .by_source
+                .entry(source)
+                .or_default()
+                .insert(key.clone());
+        }
+
+        // Packets whose send wasn't seen are counted as coming from the
+        // chain every other packet received on the same channel came from
+        let mut route_after = None;
+
+        if let Some(route) = &route {
+            if let Some(sent_on) = &sent_on {
+                *index
+                    .routes
+                    .entry(route.clone())
+                    .or_default()
+                    .entry(sent_on.clone())
+                    .or_default() += 1;
+            }
+
+            route_after = index.route(route).map(str::to_owned);
+
+            if route_after != route_before {
+                index.dst_channel_changed(&route.0, &route.2);
+            }
+        }
+
+        // A round trip is a packet whose acknowledgement was relayed back by
+        // the same team that delivered it
+        let round_trip = match (&decision, sender) {
+            (Ok(decision), Some((_, packet))) => {
+                packet.completion == Some(Completion::Acknowledged)
+                    && packet.completed_by.as_deref() == Some(decision.team.as_str())
+            }
+            _ => false,
+        };
+
+        let classified = Classified {
+            decision,
+            round_trip,
+            counterparty: sent_on
+                .clone()
+                .or(route_after)
+                .unwrap_or_else(|| UNKNOWN_COUNTERPARTY.to_owned()),
+            sent_on,
+            height: self.placed_height(&key, credit, sender),
+        };
+
+        index.tally.add(&key.chain_id, &classified);
+        index.unflushed.insert(key.clone());
+        index.classified.insert(key, classified);
+    }
+
+    fn get_team_by_address(&self, address: &str) -> Option<&String> {
+        if address.contains("cosmos1") {
+            self.address_to_team.get(address)
+        } else {
+            match decode(address) {
+                Ok((_, data)) => self.address_to_team.get(&encode("cosmos", data)),
+                Err(_) => None,
+            }
+        }
+    }
+}
+
+impl ScoringPhase for Phase2OpaquePackets {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn score_envelope(&mut self, envelope: &Envelope) {
+        let chain_id = envelope.network.to_string();
+
+        for message in envelope.msg.iter() {
+            match message {
+                sagan::message::Message::EventIBC(ref event) => {
+                    match event {
+                        // Compute all the scoring for an opaque packet
+                        IBCEvent::OpaquePacket(ref inner_event) => {
+                            status_ok!("Processing oqaque packet", " got event");
+                            // Only packets relayed while the phase was open count
+                            let scored = self
+                                .window
+                                .check(&chain_id, tx_height(&inner_event.data))
+                                .and_then(|()| {
+                                    self.track_packets(&chain_id, &inner_event.data);
+                                    self.score_opaque_packet(&chain_id, &inner_event.data)
+                                });
+
+                            if let Err(reason) = scored {
+                                status_info!(
+                                    "Skipping opaque packet",
+                                    "{} on {}",
+                                    reason,
+                                    chain_id
+                                );
+                                self.skipped.record(&chain_id, reason);
+                            }
+                        }
+                        IBCEvent::PacketTransfer(ref inner_event) => {
+                            status_ok!("Processing Packet Transfer", " got event");
+                            self.track_packets(&chain_id, &inner_event.data);
+
+                            if self.hub_id.contains(&chain_id) {
+                                if let Some(dst_channels) =
+                                    inner_event.data.get("send_packet.packet_dst_channel")
+                                {
+                                    for dst_channel in dst_channels {
+                                        // Populate the source channels data
+                                        if self.source_channels.insert(dst_channel.clone()) {
+                                            self.index.src_channel_changed(dst_channel);
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                        other => {
+                            for channel in self.clients.observe(&chain_id, other) {
+                                self.index.dst_channel_changed(&chain_id, &channel);
+                            }
+
+                            let touched = self.packets.observe(&chain_id, other);
+                            self.index.packets_changed(&touched);
+                        }
+                    }
+                }
+                _ => {
+                    continue;
+                }
+            }
+        }
+
+        self.refresh();
+    }
+
+    fn flush_ledger(&mut self, ledger: &mut Ledger) -> Result<(), Error> {
+        while let Some(key) = self.index.unflushed.iter().next().cloned() {
+            let decision = match self.index.classified.get(&key).map(|c| &c.decision) {
+                Some(Ok(decision)) => decision.clone(),
+                _ => {
+                    self.index.unflushed.remove(&key);
+                    continue;
+                }
+            };
+
+            let credit = &self.credits[&key];
+            let previous = self.ledgered.get(&key);
+
+            if previous == Some(&decision) {
+                self.index.unflushed.remove(&key);
+                continue;
+            }
+
+            if let Some(client_team) = &decision.mismatched_client {
+                status_warn!(
+                    "{} relayed packet {} of {} on a client registered to {}",
+                    decision.team,
+                    key.index,
+                    key.tx_hash,
+                    client_team
+                );
+            }
+
+            ledger.append(&LedgerEntry {
+                phase: Self::NAME.to_owned(),
+                tx_hash: key.tx_hash.clone(),
+                chain_id: key.chain_id.clone(),
+                packet_index: key.index,
+                sender: credit.sender.clone(),
+                team: decision.team.clone(),
+                attributed_by: decision.attributed_by.as_str().to_owned(),
+                category: decision.category.as_str().to_owned(),
+                packets: 1,
+                previous_team: previous.map(|previous| previous.team.clone()),
+                previous_category: previous.map(|previous| previous.category.as_str().to_owned()),
+            })?;
+
+            self.index.unflushed.remove(&key);
+            self.ledgered.insert(key, decision);
+        }
+
+        Ok(())
+    }
+
+    fn fork(&self) -> Box<dyn ScoringPhase> {
+        Box::new(Self {
+            hub_id: self.hub_id.clone(),
+            reference_chain: self.reference_chain.clone(),
+            weights: self.weights.clone(),
+            window: self.window.clone(),
+            credits: BTreeMap::new(),
+            index: Classifications::default(),
+            ledgered: BTreeMap::new(),
+            address_to_team: self.address_to_team.clone(),
+            client_to_team: self.client_to_team.clone(),
+            clients: ClientGraph::default(),
+            source_channels: BTreeSet::new(),
+            packets: PacketTracker::default(),
+            skipped: SkipCounts::default(),
+        })
+    }
+
+    fn merge(&mut self, shard: Box<dyn ScoringPhase>) {
+        let shard = shard
+            .into_any()
+            .downcast::<Self>()
+            .expect("shard forked from a different phase");
+
+        // Packets relayed in transactions already seen are duplicates
+        for (key, credit) in shard.credits {
+            match self.credits.entry(key) {
+                Entry::Occupied(entry) => {
+                    self.skipped
+                        .record(&entry.key().chain_id, SkipReason::Duplicate);
+                }
+                Entry::Vacant(entry) => {
+                    self.index.insert(entry.key(), &credit);
+                    entry.insert(credit);
+                }
+            }
+        }
+
+        for (chain_id, channel) in self.clients.merge(shard.clients) {
+            self.index.dst_channel_changed(&chain_id, &channel);
+        }
+
+        for channel in shard.source_channels {
+            if self.source_channels.insert(channel.clone()) {
+                self.index.src_channel_changed(&channel);
+            }
+        }
+
+        let touched = self.packets.merge(shard.packets);
+        self.index.packets_changed(&touched);
+        self.skipped.merge(shard.skipped);
+        self.refresh();
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+
+    fn save(&self) -> serde_json::Value {
+        serde_json::to_value(Snapshot {
+            credits: self.credits.clone(),
+            ledgered: self.ledgered.clone(),
+            clients: self.clients.clone(),
+            source_channels: self.source_channels.clone(),
+            packets: self.packets.clone(),
+            skipped: self.skipped.clone(),
+        })
+        .expect("phase 2 state is serializable")
+    }
+
+    fn restore(&mut self, state: serde_json::Value) -> Result<(), serde_json::Error> {
+        let snapshot: Snapshot = serde_json::from_value(state)?;
+
+        self.credits = snapshot.credits;
+        self.ledgered = snapshot.ledgered;
+        self.clients = snapshot.clients;
+        self.source_channels = snapshot.source_channels;
+        self.packets = snapshot.packets;
+        self.skipped = snapshot.skipped;
+
+        self.index = Classifications::default();
+
+        for (key, credit) in self.credits.iter() {
+            self.index.insert(key, credit);
+        }
+
+        self.refresh();
+
+        Ok(())
+    }
+
+    fn report(&self) -> PhaseReport {
+        let mut buf = String::new();
+        let tally = &self.index.tally;
+
+        let teams = tally
+            .scores
+            .iter()
+            .map(|(team, score)| {
+                let counterparties = tally
+                    .by_counterparty
+                    .get(team)
+                    .into_iter()
+                    .flatten()
+                    .map(|(chain_id, score)| CounterpartyScore {
+                        chain_id: chain_id.clone(),
+                        total: score.total(&self.weights),
+                        score: score.categories(),
+                    })
+                    .collect();
+
+                TeamScore::new(
+                    team.clone(),
+                    score.total(&self.weights),
+                    tally.reached_at(team),
+                    score.categories(),
+                    counterparties,
+                )
+            })
+            .collect();
+
+        for (sender_team, client_teams) in tally.client_mismatches.iter() {
+            for (client_team, count) in client_teams.iter() {
+                writeln!(
+                    &mut buf,
+                    "Team:{} relayed {} packets on a client registered to {}",
+                    sender_team, count, client_team
+                )
+                .unwrap();
+            }
+        }
+
+        let mut skipped = self.skipped.clone();
+        skipped.merge(tally.skipped.clone());
+
+        self.packets.summarize(&mut buf).unwrap();
+        skipped.summarize(&mut buf).unwrap();
+
+        let summary = buf.lines().map(str::to_owned).collect();
+
+        let mut report = PhaseReport::new(Self::NAME, "Phase 2", teams, summary);
+        report.skipped = skipped;
+        report
+    }
+
+    fn history(&self, buckets: Buckets) -> PhaseHistory {
+        // Score gained by each team in each bucket, and outside any bucket
+        let mut gained: BTreeMap<u64, BTreeMap<String, Gained>> = BTreeMap::new();
+        let mut unplaced: BTreeMap<String, Score> = BTreeMap::new();
+
+        for (key, classified) in self.index.classified.iter() {
+            let decision = match &classified.decision {
+                Ok(decision) => decision,
+                Err(_) => continue,
+            };
+
+            let at = match buckets.by {
+                BucketBy::Height => classified.height,
+                BucketBy::Time => self.credits[key].scored_at,
+            };
+
+            let score = match at {
+                Some(at) => gained
+                    .entry(buckets.until(at))
+                    .or_default()
+                    .entry(decision.team.clone())
+                    .or_default()
+                    .add(classified),
+                None => unplaced.entry(decision.team.clone()).or_default(),
+            };
+
+            score.count(decision.category, classified.round_trip);
+        }
+
+        let mut cumulative: BTreeMap<String, Gained> = BTreeMap::new();
+
+        let buckets = gained
+            .into_iter()
+            .map(|(until, teams)| {
+                for (team, gained) in teams {
+                    cumulative.entry(team).or_default().merge(&gained);
+                }
+
+                let teams = cumulative
+                    .iter()
+                    .map(|(team, gained)| {
+                        TeamScore::new(
+                            team.clone(),
+                            gained.score.total(&self.weights),
+                            gained.reached_at(),
+                            gained.score.categories(),
+                            Vec::new(),
+                        )
+                    })
+                    .collect();
+
+                Bucket { until, teams }
+            })
+            .collect();
+
+        let unplaced = unplaced
+            .into_iter()
+            .map(|(team, score)| {
+                TeamScore::new(
+                    team,
+                    score.total(&self.weights),
+                    None,
+                    score.categories(),
+                    Vec::new(),
+                )
+            })
+            .collect();
+
+        PhaseHistory {
+            phase: Self::NAME.to_owned(),
+            buckets,
+            unplaced,
+        }
+    }
+}
+
+/// Score a team gained in a bucket of the history, and how far up the
+/// reference chain its packets are placed
+#[derive(Debug, Default)]
+struct Gained {
+    score: Score,
+    /// Highest height of the reference chain a packet is placed at
+    height: Option<u64>,
+    /// Whether some packets can't be placed on the reference chain
+    unplaced: bool,
+}
+
+impl Gained {
+    /// Note where a packet is placed, returning the score to count it in
+    fn add(&mut self, classified: &Classified) -> &mut Score {
+        self.height = self.height.max(classified.height);
+        self.unplaced |= classified.height.is_none();
+        &mut self.score
+    }
+
+    /// Add what was gained in a later bucket
+    fn merge(&mut self, later: &Gained) {
+        self.score.add(&later.score);
+        self.height = self.height.max(later.height);
+        self.unplaced |= later.unplaced;
+    }
+
+    /// Height at which the score was reached, unknown like in the results
+    /// if some packets can't be placed
+    fn reached_at(&self) -> Option<u64> {
+        if self.unplaced {
+            None
+        } else {
+            self.height
+        }
+    }
+}
+
+/// Height of the block containing the transaction
+fn tx_height(data: &HashMap<String, Vec<String>>) -> Option<u64> {
+    data.get("tx.height")?.first()?.parse().ok()
+}
@@ -0,0 +1,134 @@
+//! Accounting for events which were not scored
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Reason an event was dropped without being scored
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// The phase has a window but the event has no `tx.height`
+    MissingHeight,
+    /// The event happened before the phase opened
+    BeforeWindow,
+    /// The event happened after the phase closed
+    AfterWindow,
+    /// The event has no `tx.hash`
+    MissingTxHash,
+    /// The packet was already scored
+    Duplicate,
+    /// The event has no `message.sender`
+    MissingSender,
+    /// The event has no `recv_packet.packet_src_channel`
+    MissingSrcChannel,
+    /// The packets can't be paired with the messages which carried them
+    AmbiguousSender,
+    /// The relayer address doesn't belong to any team
+    UnknownTeam,
+}
+
+impl SkipReason {
+    /// Name of this reason as it appears in reports
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SkipReason::MissingHeight => "missing_height",
+            SkipReason::BeforeWindow => "before_window",
+            SkipReason::AfterWindow => "after_window",
+            SkipReason::MissingTxHash => "missing_tx_hash",
+            SkipReason::Duplicate => "duplicate",
+            SkipReason::MissingSender => "missing_sender",
+            SkipReason::MissingSrcChannel => "missing_src_channel",
+            SkipReason::AmbiguousSender => "ambiguous_sender",
+            SkipReason::UnknownTeam => "unknown_team",
+        }
+    }
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Number of skipped events by reason, overall and for each chain
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct SkipCounts {
+    /// Skipped events on all chains
+    pub total: BTreeMap<SkipReason, u64>,
+    /// Skipped events for each chain-id
+    pub by_chain: BTreeMap<String, BTreeMap<SkipReason, u64>>,
+}
+
+impl SkipCounts {
+    /// Count an event skipped on `chain_id`
+    pub fn record(&mut self, chain_id: &str, reason: SkipReason) {
+        *self.total.entry(reason).or_default() += 1;
+        *self
+            .by_chain
+            .entry(chain_id.to_owned())
+            .or_default()
+            .entry(reason)
+            .or_default() += 1;
+    }
+
+    /// Take back an event counted by [`SkipCounts::record`], once it turned
+    /// out it can be scored or was skipped for another reason
+    pub fn forget(&mut self, chain_id: &str, reason: SkipReason) {
+        take_one(&mut self.total, &reason);
+
+        if let Some(reasons) = self.by_chain.get_mut(chain_id) {
+            take_one(reasons, &reason);
+
+            if reasons.is_empty() {
+                self.by_chain.remove(chain_id);
+            }
+        }
+    }
+
+    /// Add the counts of a shard of the inputs
+    pub fn merge(&mut self, shard: SkipCounts) {
+        for (reason, count) in shard.total {
+            *self.total.entry(reason).or_default() += count;
+        }
+
+        for (chain_id, reasons) in shard.by_chain {
+            let counts = self.by_chain.entry(chain_id).or_default();
+
+            for (reason, count) in reasons {
+                *counts.entry(reason).or_default() += count;
+            }
+        }
+    }
+
+    /// Write a summary with one line per reason and one line per chain
+    pub fn summarize(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        writeln!(f, "Unscored events: {}", self.total.values().sum::<u64>())?;
+
+        for (reason, count) in self.total.iter() {
+            writeln!(f, "  {}: {}", reason, count)?;
+        }
+
+        for (chain_id, reasons) in self.by_chain.iter() {
+            let counts: Vec<String> = reasons
+                .iter()
+                .map(|(reason, count)| format!("{}={}", reason, count))
+                .collect();
+
+            writeln!(f, "  on {}: {}", chain_id, counts.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Take one from the count of `reason`, leaving no zero count behind
+fn take_one(counts: &mut BTreeMap<SkipReason, u64>, reason: &SkipReason) {
+    if let Some(count) = counts.get_mut(reason) {
+        *count -= 1;
+
+        if *count == 0 {
+            counts.remove(reason);
+        }
+    }
+}
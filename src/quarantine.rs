@@ -0,0 +1,104 @@
+//! Quarantine of rejected input lines
+//!
+//! Lines which can't be parsed as a sagan envelope are appended to a JSONL
+//! file along with where they came from and why they were rejected, so no
+//! event is dropped without a trace.
+
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// An input line which couldn't be parsed
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Rejection {
+    /// Input the line was read from
+    pub file: PathBuf,
+    /// Line number in the input, starting from 1
+    pub line: u64,
+    /// Why the line was rejected
+    pub error: String,
+    /// Raw content of the line
+    pub content: String,
+}
+
+/// Append-only JSONL quarantine file
+///
+/// A quarantine without a file discards every rejection.
+#[derive(Debug, Default)]
+pub struct Quarantine {
+    file: Option<File>,
+}
+
+impl Quarantine {
+    /// Open the quarantine at `path`, appending to it if it already exists
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Self { file: Some(file) })
+    }
+
+    /// Append a rejected line to the quarantine
+    pub fn append(&mut self, rejection: &Rejection) -> Result<(), Error> {
+        if let Some(file) = self.file.as_mut() {
+            let mut line = serde_json::to_vec(rejection).expect("rejection is serializable");
+            line.push(b'\n');
+            file.write_all(&line)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    fn rejection(line: u64, content: &[u8]) -> Rejection {
+        Rejection {
+            file: PathBuf::from("events.jsonl"),
+            line,
+            error: "expected value at line 1 column 1".to_owned(),
+            content: String::from_utf8_lossy(content).into_owned(),
+        }
+    }
+
+    #[test]
+    fn rejections_are_appended_as_jsonl() {
+        let dir = env::temp_dir().join(format!("goz_scoring_quarantine_{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("temporary directory is writable");
+        let path = dir.join("quarantine.jsonl");
+
+        Quarantine::open(&path)
+            .and_then(|mut quarantine| quarantine.append(&rejection(3, b"{\"node\":")))
+            .unwrap();
+
+        // Reopening appends rather than replacing earlier rejections
+        Quarantine::open(&path)
+            .and_then(|mut quarantine| quarantine.append(&rejection(7, b"\xff\xfe{}")))
+            .unwrap();
+
+        let rejections = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<Rejection>(line).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(rejections.len(), 2);
+        assert_eq!(rejections[0].file, PathBuf::from("events.jsonl"));
+        assert_eq!(rejections[0].line, 3);
+        assert_eq!(rejections[0].content, "{\"node\":");
+        assert_eq!(rejections[1].line, 7);
+        assert_eq!(rejections[1].content, "\u{fffd}\u{fffd}{}");
+    }
+
+    #[test]
+    fn quarantine_without_a_file_discards_rejections() {
+        Quarantine::default()
+            .append(&rejection(1, b"not json"))
+            .unwrap();
+    }
+}
@@ -0,0 +1,780 @@
+//! Scoring reports
+//!
+//! A [`Report`] holds the results of a run independently of how they are
+//! presented, and is rendered in one of several [`Format`]s: plain text for
+//! people, JSON for the website, CSV for spreadsheets, Markdown for posting
+//! and a self-contained HTML page for publishing as is.
+//!
+//! Teams are ranked by total score. Teams with the same total share a rank
+//! unless a [`TieBreaker`] orders them.
+
+use crate::output;
+use crate::phases::SkipCounts;
+use serde::{Deserialize, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::fmt::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Style of the HTML report, inlined so the page needs nothing else
+const HTML_STYLE: &str = "\
+body { font-family: system-ui, sans-serif; color: #222; max-width: 72rem; margin: 2rem auto; padding: 0 1rem; }
+table { border-collapse: collapse; margin: 1rem 0; }
+th, td { padding: 0.3rem 0.7rem; border-bottom: 1px solid #ddd; text-align: right; }
+th { background: #f4f4f4; }
+.name { text-align: left; }
+.meta { color: #666; }
+section.team { border-left: 3px solid #ddd; padding-left: 1rem; margin: 1.5rem 0; }
+pre { background: #f4f4f4; padding: 0.7rem; overflow-x: auto; }
+";
+
+/// Results of every phase scored, and what was scored
+#[derive(Clone, Debug, Serialize)]
+pub struct Report {
+    /// Results of each phase
+    pub phases: Vec<PhaseReport>,
+    /// Inputs scored, in the order they were first scored
+    pub inputs: Vec<InputReport>,
+    /// Number of input lines rejected
+    pub rejected: u64,
+}
+
+/// Results of one phase
+#[derive(Clone, Debug, Serialize)]
+pub struct PhaseReport {
+    /// Name used to select the phase
+    pub phase: String,
+    /// Human readable name of the phase
+    pub title: String,
+    /// Score of every team, from the highest total to the lowest
+    pub teams: Vec<TeamScore>,
+    /// Notes about what was and wasn't scored, one per line
+    pub summary: Vec<String>,
+    /// Events which were not scored, for the metrics
+    #[serde(skip)]
+    pub skipped: SkipCounts,
+}
+
+/// A team's score and rank in one phase
+#[derive(Clone, Debug, Serialize)]
+pub struct TeamScore {
+    /// Rank of the team, starting from 1. Tied teams share a rank, and the
+    /// rank after them is skipped.
+    pub rank: u64,
+    /// Name of the team
+    pub team: String,
+    /// Weighted total score
+    pub total: f64,
+    /// Points behind the team ranked just above, none for the first team
+    pub gap: Option<f64>,
+    /// Percentage of the other teams ranked below this one
+    pub percentile: f64,
+    /// Block height of the reference chain at which the team reached its
+    /// score, unknown if some of its packets can't be placed there
+    pub reached_at: Option<u64>,
+    /// Count in each of the phase's scoring categories, in the phase's order
+    #[serde(serialize_with = "serialize_categories")]
+    pub score: Vec<(&'static str, u64)>,
+    /// Score broken down by the chain the team's packets came from
+    pub counterparties: Vec<CounterpartyScore>,
+}
+
+/// The part of a team's score relaying packets from one chain
+#[derive(Clone, Debug, Serialize)]
+pub struct CounterpartyScore {
+    /// Chain-id the packets were sent from
+    pub chain_id: String,
+    /// Weighted total score
+    pub total: f64,
+    /// Count in each of the phase's scoring categories, in the phase's order
+    #[serde(serialize_with = "serialize_categories")]
+    pub score: Vec<(&'static str, u64)>,
+}
+
+/// An input and how much of it was scored
+#[derive(Clone, Debug, Serialize)]
+pub struct InputReport {
+    /// Path of the input
+    pub path: PathBuf,
+    /// Lines scored
+    pub lines: u64,
+}
+
+/// How teams with the same total score are ranked
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TieBreaker {
+    /// Tied teams share a rank
+    #[default]
+    Shared,
+    /// The team which reached its score at the lowest block height of the
+    /// reference chain ranks first. Tied teams share a rank if the height
+    /// of any of them isn't known, as it can't be told which was first.
+    EarliestHeight,
+    /// Tied teams are ranked by name
+    Name,
+}
+
+impl FromStr for TieBreaker {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "shared" => Ok(TieBreaker::Shared),
+            "earliest_height" | "earliest-height" => Ok(TieBreaker::EarliestHeight),
+            "name" => Ok(TieBreaker::Name),
+            _ => Err(format!(
+                "unknown tie-breaker {:?}, expected shared, earliest_height or name",
+                s
+            )),
+        }
+    }
+}
+
+impl TieBreaker {
+    /// Order two teams with the same total score
+    fn order(self, a: &TeamScore, b: &TeamScore) -> Ordering {
+        match self {
+            TieBreaker::Shared => Ordering::Equal,
+            TieBreaker::EarliestHeight => a.reached_at.cmp(&b.reached_at),
+            TieBreaker::Name => a.team.cmp(&b.team),
+        }
+    }
+}
+
+/// How a report is rendered
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Format {
+    /// Plain text, one line per team
+    #[default]
+    Text,
+    /// A single JSON document
+    Json,
+    /// One CSV row per team and phase
+    Csv,
+    /// Markdown with a table per phase
+    Markdown,
+    /// A standalone HTML page with a table per phase and a section per team
+    Html,
+}
+
+impl Format {
+    /// Extension of files in this format
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Text => "txt",
+            Format::Json => "json",
+            Format::Csv => "csv",
+            Format::Markdown => "md",
+            Format::Html => "html",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            "markdown" => Ok(Format::Markdown),
+            "html" => Ok(Format::Html),
+            _ => Err(format!(
+                "unknown format {:?}, expected json, csv, markdown, html or text",
+                s
+            )),
+        }
+    }
+}
+
+impl TeamScore {
+    /// Unranked score of a team
+    pub fn new(
+        team: String,
+        total: f64,
+        reached_at: Option<u64>,
+        score: Vec<(&'static str, u64)>,
+        counterparties: Vec<CounterpartyScore>,
+    ) -> Self {
+        Self {
+            rank: 0,
+            team,
+            total,
+            gap: None,
+            percentile: 0.0,
+            reached_at,
+            score,
+            counterparties,
+        }
+    }
+}
+
+impl PhaseReport {
+    /// Report of a phase, with the teams ranked sharing ties
+    pub fn new(phase: &str, title: &str, teams: Vec<TeamScore>, summary: Vec<String>) -> Self {
+        let mut report = Self {
+            phase: phase.to_owned(),
+            title: title.to_owned(),
+            teams,
+            summary,
+            skipped: SkipCounts::default(),
+        };
+
+        report.rank(TieBreaker::default());
+        report
+    }
+
+    /// Rank the teams with `tie_breaker`
+    pub fn rank(&mut self, tie_breaker: TieBreaker) {
+        rank(&mut self.teams, tie_breaker);
+    }
+}
+
+impl Report {
+    /// Render the report in the given format
+    pub fn render(&self, format: Format) -> String {
+        let mut buf = String::new();
+
+        match format {
+            Format::Text => self.write_text(&mut buf),
+            Format::Json => {
+                buf = serde_json::to_string_pretty(self).expect("report is serializable");
+                buf.push('\n');
+                Ok(())
+            }
+            Format::Csv => self.write_csv(&mut buf),
+            Format::Markdown => self.write_markdown(&mut buf),
+            Format::Html => self.write_html(&mut buf),
+        }
+        .expect("writing to a String can't fail");
+
+        buf
+    }
+
+    fn write_text(&self, f: &mut impl Write) -> fmt::Result {
+        for phase in self.phases.iter() {
+            for team in phase.teams.iter() {
+                write!(
+                    f,
+                    "Rank:{}, Team:{}, Total {} Score {}, Percentile {:.1}",
+                    team.rank, team.team, phase.title, team.total, team.percentile
+                )?;
+
+                if let Some(gap) = team.gap {
+                    write!(f, ", Gap {}", gap)?;
+                }
+
+                for (category, count) in team.score.iter() {
+                    write!(f, ", {} {}", category, count)?;
+                }
+
+                writeln!(f)?;
+
+                for counterparty in team.counterparties.iter() {
+                    write!(
+                        f,
+                        "  from {}: Score {}",
+                        counterparty.chain_id, counterparty.total
+                    )?;
+
+                    for (category, count) in counterparty.score.iter() {
+                        write!(f, ", {} {}", category, count)?;
+                    }
+
+                    writeln!(f)?;
+                }
+            }
+
+            for line in phase.summary.iter() {
+                writeln!(f, "{}", line)?;
+            }
+        }
+
+        writeln!(f, "Inputs scored: {}", self.inputs.len())?;
+
+        for input in self.inputs.iter() {
+            writeln!(f, "Input:{}, Lines {}", input.path.display(), input.lines)?;
+        }
+
+        writeln!(f, "Rejected lines: {}", self.rejected)
+    }
+
+    /// One row per team and phase followed by a row for each chain the team
+    /// relayed packets from, with a column for every scoring category of any
+    /// phase
+    fn write_csv(&self, f: &mut impl Write) -> fmt::Result {
+        let mut categories: Vec<&str> = Vec::new();
+
+        for team in self.phases.iter().flat_map(|phase| phase.teams.iter()) {
+            for (category, _) in team.score.iter() {
+                if !categories.contains(category) {
+                    categories.push(category);
+                }
+            }
+        }
+
+        write!(
+            f,
+            "phase,rank,team,counterparty,total,gap,percentile,reached_at"
+        )?;
+
+        for category in categories.iter() {
+            write!(f, ",{}", csv_field(category))?;
+        }
+
+        writeln!(f)?;
+
+        for phase in self.phases.iter() {
+            for team in phase.teams.iter() {
+                write!(
+                    f,
+                    "{},{},{},,{},{},{},{}",
+                    csv_field(&phase.phase),
+                    team.rank,
+                    csv_field(&team.team),
+                    team.total,
+                    optional(team.gap),
+                    team.percentile,
+                    optional(team.reached_at)
+                )?;
+                write_csv_counts(f, &categories, &team.score)?;
+
+                for counterparty in team.counterparties.iter() {
+                    write!(
+                        f,
+                        "{},{},{},{},{},,,",
+                        csv_field(&phase.phase),
+                        team.rank,
+                        csv_field(&team.team),
+                        csv_field(&counterparty.chain_id),
+                        counterparty.total
+                    )?;
+                    write_csv_counts(f, &categories, &counterparty.score)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_markdown(&self, f: &mut impl Write) -> fmt::Result {
+        for phase in self.phases.iter() {
+            writeln!(f, "## {}", phase.title)?;
+            writeln!(f)?;
+
+            let categories: Vec<&str> = phase
+                .teams
+                .first()
+                .map(|team| team.score.iter().map(|(category, _)| *category).collect())
+                .unwrap_or_default();
+
+            write!(f, "| Rank | Team | Total | Gap | Percentile |")?;
+
+            for category in categories.iter() {
+                write!(f, " {} |", category)?;
+            }
+
+            writeln!(f)?;
+            write!(f, "|---:|---|---:|---:|---:|")?;
+
+            for _ in categories.iter() {
+                write!(f, "---:|")?;
+            }
+
+            writeln!(f)?;
+
+            for team in phase.teams.iter() {
+                write!(
+                    f,
+                    "| {} | {} | {} | {} | {:.1} |",
+                    team.rank,
+                    markdown_cell(&team.team),
+                    team.total,
+                    optional(team.gap),
+                    team.percentile
+                )?;
+
+                for (_, count) in team.score.iter() {
+                    write!(f, " {} |", count)?;
+                }
+
+                writeln!(f)?;
+            }
+
+            if phase
+                .teams
+                .iter()
+                .any(|team| !team.counterparties.is_empty())
+            {
+                writeln!(f)?;
+                writeln!(f, "### By counterparty chain")?;
+                writeln!(f)?;
+                write!(f, "| Team | From | Total |")?;
+
+                for category in categories.iter() {
+                    write!(f, " {} |", category)?;
+                }
+
+                writeln!(f)?;
+                write!(f, "|---|---|---:|")?;
+
+                for _ in categories.iter() {
+                    write!(f, "---:|")?;
+                }
+
+                writeln!(f)?;
+
+                for team in phase.teams.iter() {
+                    for counterparty in team.counterparties.iter() {
+                        write!(
+                            f,
+                            "| {} | {} | {} |",
+                            markdown_cell(&team.team),
+                            markdown_cell(&counterparty.chain_id),
+                            counterparty.total
+                        )?;
+
+                        for (_, count) in counterparty.score.iter() {
+                            write!(f, " {} |", count)?;
+                        }
+
+                        writeln!(f)?;
+                    }
+                }
+            }
+
+            if !phase.summary.is_empty() {
+                writeln!(f)?;
+                writeln!(f, "```")?;
+
+                for line in phase.summary.iter() {
+                    writeln!(f, "{}", line)?;
+                }
+
+                writeln!(f, "```")?;
+            }
+
+            writeln!(f)?;
+        }
+
+        writeln!(f, "## Inputs")?;
+        writeln!(f)?;
+
+        for input in self.inputs.iter() {
+            writeln!(f, "- `{}`: {} lines", input.path.display(), input.lines)?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "Rejected lines: {}", self.rejected)
+    }
+
+    fn write_html(&self, f: &mut impl Write) -> fmt::Result {
+        let generated_at = output::utc_timestamp(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0),
+        );
+        let lines: u64 = self.inputs.iter().map(|input| input.lines).sum();
+
+        writeln!(f, "<!DOCTYPE html>")?;
+        writeln!(f, "<html lang=\"en\">")?;
+        writeln!(f, "<head>")?;
+        writeln!(f, "<meta charset=\"utf-8\">")?;
+        writeln!(
+            f,
+            "<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">"
+        )?;
+        writeln!(f, "<title>Game of Zones results</title>")?;
+        writeln!(f, "<style>\n{}</style>", HTML_STYLE)?;
+        writeln!(f, "</head>")?;
+        writeln!(f, "<body>")?;
+        writeln!(f, "<h1>Game of Zones results</h1>")?;
+        writeln!(
+            f,
+            "<p class=\"meta\">Generated at <time datetime=\"{0}\">{0}</time> by goz_scoring {1} \
+             from {2} lines of {3} inputs, {4} lines rejected.</p>",
+            generated_at,
+            env!("CARGO_PKG_VERSION"),
+            lines,
+            self.inputs.len(),
+            self.rejected
+        )?;
+
+        for phase in self.phases.iter() {
+            let categories: Vec<&str> = phase
+                .teams
+                .first()
+                .map(|team| team.score.iter().map(|(category, _)| *category).collect())
+                .unwrap_or_default();
+
+            writeln!(f, "<section id=\"{}\">", html_text(&phase.phase))?;
+            writeln!(f, "<h2>{}</h2>", html_text(&phase.title))?;
+            writeln!(f, "<table>")?;
+            write!(
+                f,
+                "<thead><tr><th>Rank</th><th class=\"name\">Team</th><th>Total</th>\
+                 <th>Gap</th><th>Percentile</th>"
+            )?;
+
+            for category in categories.iter() {
+                write!(f, "<th>{}</th>", html_text(category))?;
+            }
+
+            writeln!(f, "</tr></thead>")?;
+            writeln!(f, "<tbody>")?;
+
+            for (i, team) in phase.teams.iter().enumerate() {
+                write!(
+                    f,
+                    "<tr><td>{}</td><td class=\"name\"><a href=\"#{}-team-{}\">{}</a></td>\
+                     <td>{}</td><td>{}</td><td>{:.1}</td>",
+                    team.rank,
+                    html_text(&phase.phase),
+                    i + 1,
+                    html_text(&team.team),
+                    team.total,
+                    optional(team.gap),
+                    team.percentile
+                )?;
+
+                for (_, count) in team.score.iter() {
+                    write!(f, "<td>{}</td>", count)?;
+                }
+
+                writeln!(f, "</tr>")?;
+            }
+
+            writeln!(f, "</tbody>")?;
+            writeln!(f, "</table>")?;
+
+            for (i, team) in phase.teams.iter().enumerate() {
+                writeln!(
+                    f,
+                    "<section class=\"team\" id=\"{}-team-{}\">",
+                    html_text(&phase.phase),
+                    i + 1
+                )?;
+                writeln!(f, "<h3>{}. {}</h3>", team.rank, html_text(&team.team))?;
+                write!(
+                    f,
+                    "<p>Total score {}, ahead of {:.1}% of the other teams",
+                    team.total, team.percentile
+                )?;
+
+                if let Some(gap) = team.gap {
+                    write!(f, ", {} behind the team ranked above", gap)?;
+                }
+
+                if let Some(reached_at) = team.reached_at {
+                    write!(f, ", reached at height {}", reached_at)?;
+                }
+
+                writeln!(f, ".</p>")?;
+                writeln!(f, "<table>")?;
+                write!(f, "<thead><tr><th class=\"name\">From</th><th>Total</th>")?;
+
+                for category in categories.iter() {
+                    write!(f, "<th>{}</th>", html_text(category))?;
+                }
+
+                writeln!(f, "</tr></thead>")?;
+                writeln!(f, "<tbody>")?;
+
+                for counterparty in team.counterparties.iter() {
+                    write!(
+                        f,
+                        "<tr><td class=\"name\">{}</td><td>{}</td>",
+                        html_text(&counterparty.chain_id),
+                        counterparty.total
+                    )?;
+
+                    for (_, count) in counterparty.score.iter() {
+                        write!(f, "<td>{}</td>", count)?;
+                    }
+
+                    writeln!(f, "</tr>")?;
+                }
+
+                write!(
+                    f,
+                    "<tr><th class=\"name\">All chains</th><th>{}</th>",
+                    team.total
+                )?;
+
+                for (_, count) in team.score.iter() {
+                    write!(f, "<th>{}</th>", count)?;
+                }
+
+                writeln!(f, "</tr>")?;
+                writeln!(f, "</tbody>")?;
+                writeln!(f, "</table>")?;
+                writeln!(f, "</section>")?;
+            }
+
+            if !phase.summary.is_empty() {
+                writeln!(f, "<h3>Notes</h3>")?;
+                writeln!(f, "<pre>")?;
+
+                for line in phase.summary.iter() {
+                    writeln!(f, "{}", html_text(line))?;
+                }
+
+                writeln!(f, "</pre>")?;
+            }
+
+            writeln!(f, "</section>")?;
+        }
+
+        writeln!(f, "<section id=\"inputs\">")?;
+        writeln!(f, "<h2>Inputs</h2>")?;
+        writeln!(f, "<table>")?;
+        writeln!(
+            f,
+            "<thead><tr><th class=\"name\">Path</th><th>Lines</th></tr></thead>"
+        )?;
+        writeln!(f, "<tbody>")?;
+
+        for input in self.inputs.iter() {
+            writeln!(
+                f,
+                "<tr><td class=\"name\">{}</td><td>{}</td></tr>",
+                html_text(&input.path.display().to_string()),
+                input.lines
+            )?;
+        }
+
+        writeln!(f, "</tbody>")?;
+        writeln!(f, "</table>")?;
+        writeln!(f, "<p>Rejected lines: {}</p>", self.rejected)?;
+        writeln!(f, "</section>")?;
+        writeln!(f, "</body>")?;
+        writeln!(f, "</html>")
+    }
+}
+
+/// Weights like 0.1 aren't exact, so round away the error sums of them
+/// carry before comparing or subtracting scores
+fn rounded(score: f64) -> f64 {
+    (score * 1e6).round() / 1e6
+}
+
+/// Rank teams from the highest total score to the lowest, ordering ties
+/// with `tie_breaker`. Teams still tied share a rank and are listed by name.
+pub fn rank(teams: &mut [TeamScore], tie_breaker: TieBreaker) {
+    // Heights only order tied teams if every one of them is known
+    let unknown_heights: Vec<f64> = teams
+        .iter()
+        .filter(|team| team.reached_at.is_none())
+        .map(|team| rounded(team.total))
+        .collect();
+
+    let tie_breaker_at = |total: f64| {
+        if tie_breaker == TieBreaker::EarliestHeight && unknown_heights.contains(&total) {
+            TieBreaker::Shared
+        } else {
+            tie_breaker
+        }
+    };
+
+    let compare = |a: &TeamScore, b: &TeamScore| {
+        rounded(b.total)
+            .partial_cmp(&rounded(a.total))
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| tie_breaker_at(rounded(a.total)).order(a, b))
+    };
+
+    teams.sort_by(|a, b| compare(a, b).then_with(|| a.team.cmp(&b.team)));
+
+    for i in 0..teams.len() {
+        let (above, rest) = teams.split_at_mut(i);
+        let team = &mut rest[0];
+
+        match above.last() {
+            Some(previous) => {
+                team.rank = if compare(previous, team) == Ordering::Equal {
+                    previous.rank
+                } else {
+                    i as u64 + 1
+                };
+                team.gap = Some(rounded(previous.total - team.total));
+            }
+            None => {
+                team.rank = 1;
+                team.gap = None;
+            }
+        }
+    }
+
+    let others = teams.len().saturating_sub(1);
+    let ranks: Vec<u64> = teams.iter().map(|team| team.rank).collect();
+
+    for team in teams.iter_mut() {
+        let below = ranks.iter().filter(|rank| **rank > team.rank).count();
+
+        team.percentile = if others == 0 {
+            100.0
+        } else {
+            below as f64 * 100.0 / others as f64
+        };
+    }
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break
+pub(crate) fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// End a CSV row with the count in each category, empty for categories
+/// the score doesn't have
+pub(crate) fn write_csv_counts(
+    f: &mut impl Write,
+    categories: &[&str],
+    score: &[(&'static str, u64)],
+) -> fmt::Result {
+    for category in categories.iter() {
+        match score.iter().find(|(name, _)| name == category) {
+            Some((_, count)) => write!(f, ",{}", count)?,
+            None => write!(f, ",")?,
+        }
+    }
+
+    writeln!(f)
+}
+
+/// A value which may be missing, as an empty string if it is
+pub(crate) fn optional(value: Option<impl fmt::Display>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+/// Escape the characters with a meaning in HTML text and attributes
+fn html_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// Escape the characters which would break a Markdown table cell
+fn markdown_cell(cell: &str) -> String {
+    cell.replace('|', "\\|")
+}
+
+/// Serialize scoring categories as an object, keeping their order
+fn serialize_categories<S>(
+    categories: &[(&'static str, u64)],
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_map(categories.iter().map(|(category, count)| (category, count)))
+}
@@ -0,0 +1,598 @@
+//! Tendermint RPC ingestion
+//!
+//! Pages through a chain's transactions over Tendermint RPC and turns the
+//! IBC packet transactions into the same sagan envelopes the collectors
+//! write, so gaps in the collected events can be backfilled and scores
+//! re-derived independently of the collectors.
+//!
+//! Transactions are found with `tx_search`, or block by block with `block`
+//! and `block_results` on nodes which don't index transactions. Their events
+//! are flattened into `type.key` attributes, as sagan does:
+//!
+//! - transactions receiving packets become `OpaquePacket` events
+//! - other transactions sending, acknowledging or timing out packets become
+//!   `PacketTransfer` events
+//!
+//! Channel and connection handshakes are not fetched.
+
+use crate::error::{Error, ErrorKind};
+use crate::phases::RECV_PACKET_ACTIONS;
+use sagan::message::Envelope;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::time::Duration;
+use subtle_encoding::{base64, hex};
+
+/// Transactions requested per `tx_search` page
+const PER_PAGE: u64 = 100;
+
+/// Timeout of each RPC request
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Client for a chain's Tendermint RPC endpoint
+#[derive(Clone, Debug)]
+pub struct RpcClient {
+    /// Base URL of the endpoint, e.g. `http://localhost:26657`
+    url: String,
+}
+
+impl RpcClient {
+    /// Create a client for the endpoint at `url`
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_owned(),
+        }
+    }
+
+    /// Chain-id and latest block height of the node
+    pub fn status(&self) -> Result<(String, u64), Error> {
+        let status: Status = self.call("status", &[])?;
+
+        Ok((
+            status.node_info.network,
+            parse_height(&status.sync_info.latest_block_height)?,
+        ))
+    }
+
+    /// Call `each` with an envelope for every IBC packet transaction between
+    /// `from` and `to` (inclusive), in block order, using `tx_search`.
+    /// Returns the number of envelopes.
+    pub fn search_txs(
+        &self,
+        chain_id: &str,
+        from: u64,
+        to: u64,
+        mut each: impl FnMut(Envelope),
+    ) -> Result<u64, Error> {
+        let query = format!("\"tx.height>={} AND tx.height<={}\"", from, to);
+        let mut envelopes = 0;
+        let mut searched = 0;
+        let mut page = 1;
+
+        loop {
+            let search: TxSearch = self.call(
+                "tx_search",
+                &[
+                    ("query", query.clone()),
+                    ("page", page.to_string()),
+                    ("per_page", PER_PAGE.to_string()),
+                    ("order_by", "\"asc\"".to_owned()),
+                ],
+            )?;
+
+            for tx in search.txs.iter() {
+                if let Some(envelope) =
+                    self.envelope(chain_id, &tx.hash, &tx.height, &tx.tx_result)?
+                {
+                    each(envelope);
+                    envelopes += 1;
+                }
+            }
+
+            let total: u64 = search.total_count.parse().map_err(|_| {
+                ErrorKind::Rpc.context(format!("invalid total_count {:?}", search.total_count))
+            })?;
+
+            // Nodes may return fewer transactions per page than requested
+            searched += search.txs.len() as u64;
+
+            if search.txs.is_empty() || searched >= total {
+                return Ok(envelopes);
+            }
+
+            page += 1;
+        }
+    }
+
+    /// Call `each` with an envelope for every IBC packet transaction between
+    /// `from` and `to` (inclusive), in block order, using `block` and
+    /// `block_results`. Returns the number of envelopes.
+    pub fn scan_blocks(
+        &self,
+        chain_id: &str,
+        from: u64,
+        to: u64,
+        mut each: impl FnMut(Envelope),
+    ) -> Result<u64, Error> {
+        let mut envelopes = 0;
+
+        for height in from..=to {
+            let params = [("height", height.to_string())];
+            let block: BlockResponse = self.call("block", &params)?;
+            let results: BlockResults = self.call("block_results", &params)?;
+
+            let txs = block.block.data.txs.unwrap_or_default();
+            let tx_results = results.txs_results.unwrap_or_default();
+
+            for (tx, tx_result) in txs.iter().zip(tx_results.iter()) {
+                let hash = tx_hash(tx)?;
+
+                if let Some(envelope) =
+                    self.envelope(chain_id, &hash, &height.to_string(), tx_result)?
+                {
+                    each(envelope);
+                    envelopes += 1;
+                }
+            }
+        }
+
+        Ok(envelopes)
+    }
+
+    /// Envelope carrying a transaction's flattened events, if it relayed
+    /// any packets
+    fn envelope(
+        &self,
+        chain_id: &str,
+        hash: &str,
+        height: &str,
+        tx_result: &TxResult,
+    ) -> Result<Option<Envelope>, Error> {
+        // Failed transactions didn't relay anything
+        if tx_result.code != 0 {
+            return Ok(None);
+        }
+
+        let mut data: HashMap<String, Vec<String>> = HashMap::new();
+        data.insert("tx.hash".to_owned(), vec![hash.to_owned()]);
+        data.insert("tx.height".to_owned(), vec![height.to_owned()]);
+
+        for event in tx_result.events.iter() {
+            for attribute in event.attributes.iter() {
+                let (key, value) = attribute.decode();
+                data.entry(format!("{}.{}", event.kind, key))
+                    .or_default()
+                    .push(value);
+            }
+        }
+
+        let receives = data.contains_key("recv_packet.packet_sequence")
+            || data
+                .get("message.action")
+                .into_iter()
+                .flatten()
+                .any(|action| RECV_PACKET_ACTIONS.contains(&action.as_str()));
+
+        let kind = if receives {
+            "OpaquePacket"
+        } else if ["send_packet", "acknowledge_packet", "timeout_packet"]
+            .iter()
+            .any(|prefix| data.contains_key(&format!("{}.packet_sequence", prefix)))
+        {
+            "PacketTransfer"
+        } else {
+            return Ok(None);
+        };
+
+        let envelope = serde_json::json!({
+            "node": self.url,
+            "network": chain_id,
+            "msg": [{ "EventIBC": { kind: { "data": data } } }],
+        });
+
+        serde_json::from_value(envelope)
+            .map(Some)
+            .map_err(|e| ErrorKind::Rpc.context(e).into())
+    }
+
+    /// Call an RPC method with URI parameters and return its result
+    fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: &[(&str, String)],
+    ) -> Result<T, Error> {
+        let mut request = ureq::get(&format!("{}/{}", self.url, method));
+        request.timeout(TIMEOUT);
+
+        for (name, value) in params {
+            request.query(name, value);
+        }
+
+        let response = request.call();
+
+        if let Some(e) = response.synthetic_error() {
+            return Err(ErrorKind::Rpc.context(format!("{}: {}", method, e)).into());
+        }
+
+        let body = response
+            .into_string()
+            .map_err(|e| ErrorKind::Rpc.context(format!("{}: {}", method, e)))?;
+
+        let body: RpcResponse<T> = serde_json::from_str(&body)
+            .map_err(|e| ErrorKind::Rpc.context(format!("{}: {}", method, e)))?;
+
+        match (body.result, body.error) {
+            (Some(result), _) => Ok(result),
+            (None, Some(error)) => Err(ErrorKind::Rpc
+                .context(format!("{}: {}", method, error))
+                .into()),
+            (None, None) => Err(ErrorKind::Rpc
+                .context(format!("{}: response without a result", method))
+                .into()),
+        }
+    }
+}
+
+/// Hash of a base64 encoded transaction, as reported by `tx_search`
+fn tx_hash(tx: &str) -> Result<String, Error> {
+    let bytes = base64::decode(tx).map_err(|e| ErrorKind::Rpc.context(e))?;
+    let hash = hex::encode_upper(Sha256::digest(&bytes));
+
+    Ok(String::from_utf8(hash).expect("hex is valid UTF-8"))
+}
+
+fn parse_height(height: &str) -> Result<u64, Error> {
+    height.parse().map_err(|_| {
+        ErrorKind::Rpc
+            .context(format!("invalid height {:?}", height))
+            .into()
+    })
+}
+
+/// JSON-RPC response
+#[derive(Deserialize)]
+struct RpcResponse<T> {
+    result: Option<T>,
+    error: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct Status {
+    node_info: NodeInfo,
+    sync_info: SyncInfo,
+}
+
+#[derive(Deserialize)]
+struct NodeInfo {
+    network: String,
+}
+
+#[derive(Deserialize)]
+struct SyncInfo {
+    latest_block_height: String,
+}
+
+#[derive(Deserialize)]
+struct TxSearch {
+    txs: Vec<TxResponse>,
+    total_count: String,
+}
+
+#[derive(Deserialize)]
+struct TxResponse {
+    hash: String,
+    height: String,
+    tx_result: TxResult,
+}
+
+#[derive(Deserialize)]
+struct TxResult {
+    #[serde(default)]
+    code: u32,
+    #[serde(default)]
+    events: Vec<Event>,
+}
+
+#[derive(Deserialize)]
+struct Event {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    attributes: Vec<Attribute>,
+}
+
+/// Event attribute. Tendermint 0.33 and 0.34 encode keys and values as
+/// base64, later versions don't.
+#[derive(Deserialize)]
+struct Attribute {
+    key: Option<String>,
+    value: Option<String>,
+}
+
+impl Attribute {
+    /// Key and value as plain text.
+    ///
+    /// Attributes are taken to be base64 encoded when their key decodes to
+    /// an identifier, which plain keys like `sender` never do.
+    fn decode(&self) -> (String, String) {
+        let key = self.key.clone().unwrap_or_default();
+        let value = self.value.clone().unwrap_or_default();
+
+        match decode_base64(&key) {
+            Some(decoded) if is_identifier(&decoded) => {
+                let value = decode_base64(&value).unwrap_or(value);
+                (decoded, value)
+            }
+            _ => (key, value),
+        }
+    }
+}
+
+fn decode_base64(encoded: &str) -> Option<String> {
+    base64::decode(encoded)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+}
+
+fn is_identifier(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+#[derive(Deserialize)]
+struct BlockResponse {
+    block: Block,
+}
+
+#[derive(Deserialize)]
+struct Block {
+    data: BlockData,
+}
+
+#[derive(Deserialize)]
+struct BlockData {
+    txs: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+struct BlockResults {
+    txs_results: Option<Vec<TxResult>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use tiny_http::{Response, Server};
+
+    /// Tendermint RPC endpoint answering every request with the result
+    /// `respond` gives for its method and parameters. Returns its URL and
+    /// the requests it has answered.
+    fn mock_node(
+        respond: impl Fn(&str, &HashMap<String, String>) -> Value + Send + 'static,
+    ) -> (String, Arc<Mutex<Vec<String>>>) {
+        let server = Server::http("127.0.0.1:0").expect("can listen on localhost");
+        let url = format!("http://{}", server.server_addr());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let answered = Arc::clone(&requests);
+
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let url = request.url().to_owned();
+                let mut parts = url.trim_start_matches('/').splitn(2, '?');
+                let method = parts.next().unwrap_or_default().to_owned();
+                let params = parts
+                    .next()
+                    .unwrap_or_default()
+                    .split('&')
+                    .filter_map(|param| {
+                        let mut param = param.splitn(2, '=');
+                        Some((param.next()?.to_owned(), param.next()?.to_owned()))
+                    })
+                    .collect();
+
+                let body =
+                    json!({ "jsonrpc": "2.0", "id": -1, "result": respond(&method, &params) });
+                answered.lock().unwrap().push(url);
+                let _ = request.respond(Response::from_string(body.to_string()));
+            }
+        });
+
+        (url, requests)
+    }
+
+    fn attribute(key: &str, value: &str) -> Value {
+        json!({ "key": key, "value": value })
+    }
+
+    /// Result of a transaction with the given events and result code
+    fn tx_result(code: u32, events: Vec<(&str, Vec<Value>)>) -> Value {
+        let events: Vec<Value> = events
+            .into_iter()
+            .map(|(kind, attributes)| json!({ "type": kind, "attributes": attributes }))
+            .collect();
+
+        json!({ "code": code, "events": events })
+    }
+
+    /// Transaction delivering a packet, with plain attributes
+    fn recv_result() -> Value {
+        tx_result(
+            0,
+            vec![
+                (
+                    "message",
+                    vec![
+                        attribute("action", "ics04/opaque"),
+                        attribute("sender", "cosmos1alpha"),
+                    ],
+                ),
+                (
+                    "recv_packet",
+                    vec![
+                        attribute("packet_src_channel", "ibczone"),
+                        attribute("packet_sequence", "1"),
+                    ],
+                ),
+            ],
+        )
+    }
+
+    /// Transaction sending a packet, with plain attributes
+    fn send_result() -> Value {
+        tx_result(
+            0,
+            vec![
+                ("message", vec![attribute("action", "transfer")]),
+                ("send_packet", vec![attribute("packet_sequence", "2")]),
+            ],
+        )
+    }
+
+    fn kind(envelope: &Envelope) -> String {
+        let envelope = serde_json::to_value(envelope).unwrap();
+        let event = envelope["msg"][0]["EventIBC"].as_object().unwrap();
+        event.keys().next().unwrap().clone()
+    }
+
+    fn data(envelope: &Envelope) -> Value {
+        let envelope = serde_json::to_value(envelope).unwrap();
+        let event = envelope["msg"][0]["EventIBC"].as_object().unwrap();
+        event.values().next().unwrap()["data"].clone()
+    }
+
+    #[test]
+    fn attributes_are_decoded_from_base64_when_encoded() {
+        // Tendermint 0.33
+        let encoded = Attribute {
+            key: Some("c2VuZGVy".to_owned()),
+            value: Some("Y29zbW9zMWFscGhh".to_owned()),
+        };
+        assert_eq!(
+            encoded.decode(),
+            ("sender".to_owned(), "cosmos1alpha".to_owned())
+        );
+
+        // Later versions
+        let plain = Attribute {
+            key: Some("action".to_owned()),
+            value: Some("ics04/opaque".to_owned()),
+        };
+        assert_eq!(
+            plain.decode(),
+            ("action".to_owned(), "ics04/opaque".to_owned())
+        );
+
+        assert!(is_identifier("packet_src_channel"));
+        assert!(!is_identifier(""));
+        assert!(!is_identifier("i\u{b7}&"));
+    }
+
+    #[test]
+    fn hashes_are_those_tx_search_reports() {
+        assert_eq!(
+            tx_hash("cmVsYXllZCB0eA==").unwrap(),
+            "59A244886B3F6E7DD63BB74A67A1C8AD1070A5D07DB5883EE91E425B9251280C"
+        );
+        assert!(tx_hash("not base64!").is_err());
+    }
+
+    #[test]
+    fn transactions_are_classified_by_their_packets() {
+        let (url, _) = mock_node(|_, _| {
+            // Encoded like Tendermint 0.33
+            let encoded_recv = tx_result(
+                0,
+                vec![
+                    ("message", vec![attribute("YWN0aW9u", "cmVjdl9wYWNrZXQ=")]),
+                    (
+                        "recv_packet",
+                        vec![attribute("cGFja2V0X3NlcXVlbmNl", "MQ==")],
+                    ),
+                ],
+            );
+            let failed = tx_result(
+                1,
+                vec![("recv_packet", vec![attribute("packet_sequence", "3")])],
+            );
+            let unrelated = tx_result(0, vec![("message", vec![attribute("action", "send")])]);
+
+            let txs: Vec<Value> = vec![recv_result(), send_result(), encoded_recv, failed, unrelated]
+                .into_iter()
+                .enumerate()
+                .map(|(index, tx_result)| {
+                    json!({ "hash": format!("TX{}", index), "height": "7", "tx_result": tx_result })
+                })
+                .collect();
+
+            json!({ "txs": txs, "total_count": "5" })
+        });
+
+        let mut envelopes = Vec::new();
+        let count = RpcClient::new(&url)
+            .search_txs("zone", 1, 10, |envelope| envelopes.push(envelope))
+            .unwrap();
+
+        assert_eq!(count, 3);
+        assert_eq!(
+            envelopes.iter().map(kind).collect::<Vec<_>>(),
+            vec!["OpaquePacket", "PacketTransfer", "OpaquePacket"]
+        );
+
+        let recv = data(&envelopes[0]);
+        assert_eq!(recv["tx.hash"], json!(["TX0"]));
+        assert_eq!(recv["tx.height"], json!(["7"]));
+        assert_eq!(recv["message.sender"], json!(["cosmos1alpha"]));
+        assert_eq!(recv["recv_packet.packet_src_channel"], json!(["ibczone"]));
+        assert_eq!(
+            data(&envelopes[2])["message.action"],
+            json!(["recv_packet"])
+        );
+    }
+
+    #[test]
+    fn tx_search_pages_until_every_transaction_is_seen() {
+        // Serves fewer transactions per page than asked for, then runs out
+        // before the total it reported
+        let (url, requests) = mock_node(|_, params| {
+            let txs: Vec<Value> = match params.get("page").map(String::as_str) {
+                Some("1") => vec![recv_result(), send_result()],
+                Some("2") => vec![recv_result()],
+                _ => Vec::new(),
+            }
+            .into_iter()
+            .map(|tx_result| json!({ "hash": "TX", "height": "7", "tx_result": tx_result }))
+            .collect();
+
+            json!({ "txs": txs, "total_count": "5" })
+        });
+
+        let count = RpcClient::new(&url)
+            .search_txs("zone", 1, 10, |_| ())
+            .unwrap();
+
+        assert_eq!(count, 3);
+        assert_eq!(requests.lock().unwrap().len(), 3);
+
+        // Stops as soon as the total has been seen
+        let (url, requests) = mock_node(|_, _| {
+            json!({
+                "txs": [{ "hash": "TX", "height": "7", "tx_result": send_result() }],
+                "total_count": "2",
+            })
+        });
+
+        let count = RpcClient::new(&url)
+            .search_txs("zone", 1, 10, |_| ())
+            .unwrap();
+
+        assert_eq!(count, 2);
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+}
@@ -18,9 +18,13 @@
     unused_qualifications
 )]
 
+mod support;
+
 use abscissa_core::testing::prelude::*;
 use goz_scoring::config::GozScoringConfig;
 use once_cell::sync::Lazy;
+use std::fs;
+use support::{config, recv, scratch_dir, send};
 
 /// Executes your application binary via `cargo run`.
 ///
@@ -89,3 +93,91 @@ fn version_no_args() {
     let mut cmd = runner.arg("version").capture_stdout().run();
     cmd.stdout().expect_regex(r"\A\w+ [\d\.\-]+\z");
 }
+
+/// Line of an event file in which `relayer` delivers packet `sequence` to
+/// `chain_id`
+fn receipt_line(chain_id: &str, relayer: &str, sequence: u64) -> String {
+    let hash = format!("{}recv{}", chain_id, sequence);
+    recv(chain_id, &hash, relayer, "ibczone", "ibczone", sequence).to_string()
+}
+
+/// Line of an event file in which `chain_id` sends packet `sequence`
+fn send_line(chain_id: &str, sequence: u64) -> String {
+    let hash = format!("{}send{}", chain_id, sequence);
+    send(chain_id, &hash, "ibczone", "ibczone", sequence).to_string()
+}
+
+/// Scoring with `--jobs` writes the same results and audit ledger as scoring
+/// the event files one after the other, even when the Hub's sends are only
+/// seen after the packets they carried were credited
+#[test]
+fn start_jobs_match_sequential() {
+    let dir = scratch_dir("jobs");
+
+    let files = [
+        (
+            "zone.jsonl",
+            vec![
+                receipt_line("zone", "cosmos1alpha", 1),
+                receipt_line("zone", "cosmos1beta", 2),
+                send_line("zone", 1),
+            ],
+        ),
+        (
+            "hub.jsonl",
+            vec![
+                receipt_line("hub", "cosmos1beta", 1),
+                send_line("hub", 1),
+                send_line("hub", 2),
+            ],
+        ),
+        ("late.jsonl", vec![receipt_line("zone", "cosmos1beta", 3)]),
+    ];
+
+    let mut inputs = Vec::new();
+
+    for (name, lines) in files.iter() {
+        let path = dir.join(name);
+        fs::write(&path, lines.join("\n") + "\n").unwrap();
+        inputs.push(path.display().to_string());
+    }
+
+    let run = |jobs: &str| {
+        let output = dir.join(format!("results-{}.json", jobs));
+        let ledger = dir.join(format!("ledger-{}.jsonl", jobs));
+
+        let mut args = vec![
+            "start".to_owned(),
+            "--jobs".to_owned(),
+            jobs.to_owned(),
+            "--format".to_owned(),
+            "json".to_owned(),
+            "--output".to_owned(),
+            output.display().to_string(),
+            "--ledger".to_owned(),
+            ledger.display().to_string(),
+        ];
+        args.extend(inputs.iter().cloned());
+
+        let mut runner = RUNNER.clone();
+        runner
+            .config(&config())
+            .args(&args)
+            .run()
+            .wait()
+            .unwrap()
+            .expect_success();
+
+        (
+            fs::read_to_string(&output).unwrap(),
+            fs::read_to_string(&ledger).unwrap(),
+        )
+    };
+
+    let (results, ledger) = run("1");
+    assert!(results.contains("\"packets_from_hub\": 2"));
+    assert_eq!(ledger.lines().count(), 6);
+
+    assert_eq!(run("3"), (results.clone(), ledger.clone()));
+    assert_eq!(run("2"), (results, ledger));
+}
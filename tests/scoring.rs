@@ -0,0 +1,527 @@
+//! Scoring tests: score fixtures of envelopes through the phases directly
+//! and check the results don't depend on how the inputs were scored.
+
+#![forbid(unsafe_code)]
+#![warn(
+    rust_2018_idioms,
+    trivial_casts,
+    unused_lifetimes,
+    unused_qualifications
+)]
+
+mod support;
+
+use goz_scoring::checkpoint::{self, Checkpoint};
+use goz_scoring::history::{BucketBy, Buckets};
+use goz_scoring::ledger::Ledger;
+use goz_scoring::phases::{self, ScoringPhase, SkipReason};
+use goz_scoring::report::{self, Format, PhaseReport, Report, TeamScore, TieBreaker};
+use sagan::message::Envelope;
+use serde_json::{json, Value};
+use std::fs;
+use support::{ack, channel_open, config, connection_open, recv, scratch_dir, send};
+
+/// Score the envelopes in order through a new Phase 2
+fn score<'a>(envelopes: impl IntoIterator<Item = &'a Value>) -> Box<dyn ScoringPhase> {
+    let mut phase = phases::build("phase2", &config()).expect("phase2 exists");
+    score_into(phase.as_mut(), envelopes);
+    phase
+}
+
+/// Score the envelopes in order through `phase`
+fn score_into<'a>(phase: &mut dyn ScoringPhase, envelopes: impl IntoIterator<Item = &'a Value>) {
+    for envelope in envelopes {
+        let envelope: Envelope =
+            serde_json::from_value(envelope.clone()).expect("fixture envelopes are valid");
+        phase.score_envelope(&envelope);
+    }
+}
+
+/// Report of a phase rendered as JSON
+fn render(phase: &dyn ScoringPhase) -> String {
+    Report {
+        phases: vec![phase.report()],
+        inputs: Vec::new(),
+        rejected: 0,
+    }
+    .render(Format::Json)
+}
+
+/// Count credited to a team in one category
+fn count(report: &PhaseReport, team: &str, category: &str) -> u64 {
+    report
+        .teams
+        .iter()
+        .find(|score| score.team == team)
+        .and_then(|score| score.score.iter().find(|(name, _)| *name == category))
+        .map(|(_, count)| *count)
+        .unwrap_or_default()
+}
+
+/// Every ordering of `0..n`
+fn orderings(n: usize) -> Vec<Vec<usize>> {
+    if n == 0 {
+        return vec![Vec::new()];
+    }
+
+    let mut all = Vec::new();
+
+    for ordering in orderings(n - 1) {
+        for position in 0..=ordering.len() {
+            let mut ordering = ordering.clone();
+            ordering.insert(position, n - 1);
+            all.push(ordering);
+        }
+    }
+
+    all
+}
+
+/// A packet sent from a zone to the Hub and acknowledged, one sent from the
+/// Hub to the zone, and a duplicate of the first receipt
+fn round_trip_fixture() -> Vec<Value> {
+    vec![
+        send("zone", "zonesend", "ibczone", "ibczone", 1),
+        recv("hub", "hubrecv", "cosmos1alpha", "ibczone", "ibczone", 1),
+        ack("zone", "zoneack", "cosmos1alpha", "ibczone", "ibczone", 1),
+        send("hub", "hubsend", "ibczone", "ibczone", 1),
+        recv("zone", "zonerecv", "cosmos1beta", "ibczone", "ibczone", 1),
+        recv("hub", "hubrecv", "cosmos1alpha", "ibczone", "ibczone", 1),
+    ]
+}
+
+#[test]
+fn every_ordering_scores_the_same() {
+    let fixture = round_trip_fixture();
+    let expected = score(fixture.iter());
+    let report = expected.report();
+
+    assert_eq!(count(&report, "alpha", "hub_opaque_packets"), 1);
+    assert_eq!(count(&report, "alpha", "round_trips"), 1);
+    assert_eq!(count(&report, "beta", "packets_from_hub"), 1);
+
+    let expected = render(expected.as_ref());
+
+    for ordering in orderings(fixture.len()) {
+        let phase = score(ordering.iter().map(|&index| &fixture[index]));
+        assert_eq!(render(phase.as_ref()), expected, "ordering {:?}", ordering);
+    }
+}
+
+/// Packets credited to a team from each chain
+fn counterparties(report: &PhaseReport, team: &str) -> Vec<(String, u64)> {
+    report
+        .teams
+        .iter()
+        .find(|score| score.team == team)
+        .into_iter()
+        .flat_map(|score| score.counterparties.iter())
+        .map(|counterparty| {
+            let packets = counterparty
+                .score
+                .iter()
+                .find(|(name, _)| *name == "opaque_packets_total")
+                .map(|(_, count)| *count)
+                .unwrap_or_default();
+
+            (counterparty.chain_id.clone(), packets)
+        })
+        .collect()
+}
+
+/// Two zones sending on the same channel id with the same sequences to
+/// different Hub channels, and the Hub sending back to both. The second
+/// packet from each zone is credited before its send is seen, if ever.
+fn shared_channel_fixture() -> Vec<Value> {
+    vec![
+        send("zone1", "zone1send", "channel-0", "channel-1", 1),
+        send("zone2", "zone2send", "channel-0", "channel-2", 1),
+        recv(
+            "hub",
+            "hubrecv1",
+            "cosmos1alpha",
+            "channel-0",
+            "channel-1",
+            1,
+        ),
+        recv(
+            "hub",
+            "hubrecv2",
+            "cosmos1beta",
+            "channel-0",
+            "channel-2",
+            1,
+        ),
+        recv(
+            "hub",
+            "hubrecv3",
+            "cosmos1alpha",
+            "channel-0",
+            "channel-1",
+            2,
+        ),
+        recv(
+            "hub",
+            "hubrecv4",
+            "cosmos1beta",
+            "channel-0",
+            "channel-2",
+            2,
+        ),
+        send("hub", "hubsend1", "channel-1", "channel-0", 1),
+        send("hub", "hubsend2", "channel-2", "channel-0", 1),
+        recv(
+            "zone1",
+            "zone1recv",
+            "cosmos1alpha",
+            "channel-1",
+            "channel-0",
+            1,
+        ),
+        recv(
+            "zone2",
+            "zone2recv",
+            "cosmos1beta",
+            "channel-2",
+            "channel-0",
+            1,
+        ),
+    ]
+}
+
+#[test]
+fn counterparties_of_zones_sharing_channel_ids() {
+    let fixture = shared_channel_fixture();
+
+    for phase in [score(fixture.iter()), score(fixture.iter().rev())] {
+        let report = phase.report();
+
+        assert_eq!(
+            counterparties(&report, "alpha"),
+            vec![("hub".to_owned(), 1), ("zone1".to_owned(), 2)]
+        );
+        assert_eq!(
+            counterparties(&report, "beta"),
+            vec![("hub".to_owned(), 1), ("zone2".to_owned(), 2)]
+        );
+    }
+}
+
+/// Clients opened on the Hub and on a zone with the same client-id, and
+/// packets received on top of them
+fn client_fixture() -> Vec<Value> {
+    vec![
+        connection_open("hub", "connection-0", "alphaclient"),
+        channel_open("hub", "channel-0", "connection-0"),
+        connection_open("zone", "connection-0", "alphaclient"),
+        channel_open("zone", "channel-0", "connection-0"),
+        recv(
+            "hub",
+            "clientrecv1",
+            "cosmos1stranger",
+            "channel-9",
+            "channel-0",
+            1,
+        ),
+        recv(
+            "hub",
+            "clientrecv2",
+            "cosmos1beta",
+            "channel-9",
+            "channel-0",
+            2,
+        ),
+        recv(
+            "zone",
+            "clientrecv3",
+            "cosmos1stranger",
+            "channel-9",
+            "channel-0",
+            1,
+        ),
+    ]
+}
+
+/// Packets from relayers outside the teams are credited to the team whose
+/// Hub client received them, and packets a team relayed on another team's
+/// client are reported. A zone's client with the same id credits no one.
+#[test]
+fn hub_clients_credit_their_team() {
+    let phase = score(client_fixture().iter());
+    let report = phase.report();
+
+    assert_eq!(count(&report, "alpha", "hub_opaque_packets"), 1);
+    assert_eq!(count(&report, "alpha", "opaque_packets_total"), 1);
+    assert_eq!(count(&report, "beta", "hub_opaque_packets"), 1);
+    assert!(report
+        .summary
+        .contains(&"Team:beta relayed 1 packets on a client registered to alpha".to_owned()));
+    assert_eq!(
+        report.skipped.by_chain["zone"].get(&SkipReason::UnknownTeam),
+        Some(&1)
+    );
+}
+
+/// Packets are classified again as the events they depend on are seen,
+/// which gives the same results as classifying everything at once
+#[test]
+fn updated_classifications_match_classifying_from_scratch() {
+    let fixture: Vec<Value> = client_fixture()
+        .into_iter()
+        .chain(shared_channel_fixture())
+        .chain(round_trip_fixture())
+        // Sent on a channel the Hub sends on, though this send wasn't seen
+        .chain(vec![recv(
+            "zone1",
+            "zone1recv2",
+            "cosmos1alpha",
+            "channel-0",
+            "channel-7",
+            5,
+        )])
+        .collect();
+    let expected = render(score(fixture.iter()).as_ref());
+
+    for rotation in 0..fixture.len() {
+        let mut envelopes = fixture.clone();
+        envelopes.rotate_left(rotation);
+
+        for envelopes in [envelopes.clone(), envelopes.into_iter().rev().collect()] {
+            let phase = score(envelopes.iter());
+            let mut from_scratch = score(&[]);
+            from_scratch.restore(phase.save()).unwrap();
+
+            assert_eq!(render(phase.as_ref()), expected, "rotation {}", rotation);
+            assert_eq!(render(from_scratch.as_ref()), expected);
+        }
+    }
+}
+
+/// A saved checkpoint restores the scoring state it was taken from, so
+/// scoring the rest of the events after it gives the same results
+#[test]
+fn resuming_from_a_checkpoint_scores_like_one_run() {
+    let fixture = round_trip_fixture();
+    let (before, after) = fixture.split_at(fixture.len() / 2);
+    let dir = scratch_dir("checkpoint");
+    let path = dir.join("checkpoint.json");
+
+    let phase = score(before.iter());
+    let mut checkpoint = Checkpoint::default();
+    checkpoint
+        .phases
+        .insert(phase.name().to_owned(), phase.save());
+    checkpoint.rejected = 2;
+    checkpoint.save(&path).unwrap();
+
+    let loaded = Checkpoint::load(&path).unwrap();
+    assert_eq!(loaded.rejected, 2);
+
+    let mut resumed = score(&[]);
+    resumed
+        .restore(loaded.phases[phase.name()].clone())
+        .unwrap();
+    score_into(resumed.as_mut(), after.iter());
+
+    assert_eq!(
+        render(resumed.as_ref()),
+        render(score(fixture.iter()).as_ref())
+    );
+
+    // Checkpoints of another format version are refused
+    let mut json: Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+    json["version"] = json!(checkpoint::VERSION - 1);
+    fs::write(&path, json.to_string()).unwrap();
+    assert!(Checkpoint::load(&path).is_err());
+}
+
+/// Totals which only differ by the error of inexact weights are tied
+#[test]
+fn inexact_totals_tie() {
+    let mut teams = vec![
+        TeamScore::new(
+            "alpha".to_owned(),
+            0.1 + 0.2,
+            Some(30),
+            Vec::new(),
+            Vec::new(),
+        ),
+        TeamScore::new("beta".to_owned(), 0.3, Some(20), Vec::new(), Vec::new()),
+    ];
+
+    report::rank(&mut teams, TieBreaker::Shared);
+    assert_eq!(teams[0].rank, 1);
+    assert_eq!(teams[1].rank, 1);
+
+    report::rank(&mut teams, TieBreaker::EarliestHeight);
+    assert_eq!(teams[0].team, "beta");
+    assert_eq!(teams[1].rank, 2);
+}
+
+/// Tied teams can't be ordered by height if one of them relayed packets
+/// which can't be placed on the reference chain
+#[test]
+fn unknown_heights_share_a_rank() {
+    let team = |name: &str, reached_at| {
+        TeamScore::new(name.to_owned(), 1.0, reached_at, Vec::new(), Vec::new())
+    };
+    let mut teams = vec![
+        team("alpha", Some(30)),
+        team("beta", None),
+        team("gamma", Some(20)),
+        TeamScore::new("delta".to_owned(), 0.5, Some(40), Vec::new(), Vec::new()),
+        TeamScore::new("epsilon".to_owned(), 0.5, Some(10), Vec::new(), Vec::new()),
+    ];
+
+    report::rank(&mut teams, TieBreaker::EarliestHeight);
+
+    let ranks: Vec<(&str, u64)> = teams
+        .iter()
+        .map(|team| (team.team.as_str(), team.rank))
+        .collect();
+    assert_eq!(
+        ranks,
+        vec![
+            ("alpha", 1),
+            ("beta", 1),
+            ("gamma", 1),
+            ("epsilon", 4),
+            ("delta", 5)
+        ]
+    );
+}
+
+/// Packets are placed on the reference chain, the Hub, at the height they
+/// were received there, or sent from or acknowledged on it
+#[test]
+fn packets_are_placed_on_the_reference_chain() {
+    let phase = score(round_trip_fixture().iter());
+    let report = phase.report();
+    let reached_at = |team: &str| {
+        report
+            .teams
+            .iter()
+            .find(|score| score.team == team)
+            .and_then(|score| score.reached_at)
+    };
+
+    // Received on the Hub
+    assert_eq!(reached_at("alpha"), Some(20));
+    // Received on the zone, sent from the Hub
+    assert_eq!(reached_at("beta"), Some(10));
+
+    let history = phase.history(Buckets {
+        by: BucketBy::Height,
+        size: 100,
+    });
+
+    assert_eq!(history.buckets.len(), 1);
+    assert_eq!(history.buckets[0].until, 99);
+    assert_eq!(history.buckets[0].teams.len(), 2);
+    assert!(history.unplaced.is_empty());
+
+    // A packet between zones can't be placed
+    let mut fixture = round_trip_fixture();
+    fixture.push(send("zone2", "zone2send", "ibczone", "ibczone", 9));
+    fixture.push(recv(
+        "zone",
+        "zonerecv9",
+        "cosmos1beta",
+        "ibczone",
+        "ibczone",
+        9,
+    ));
+    let phase = score(fixture.iter());
+    let report = phase.report();
+    let beta = report.teams.iter().find(|score| score.team == "beta");
+    assert_eq!(beta.and_then(|score| score.reached_at), None);
+
+    // The last bucket and the unplaced packets add up to the results
+    let history = phase.history(Buckets {
+        by: BucketBy::Height,
+        size: 100,
+    });
+    let last = history.buckets.last().unwrap();
+    assert_eq!(history.unplaced.len(), 1);
+    assert_eq!(history.unplaced[0].team, "beta");
+
+    for team in report.teams.iter() {
+        let total: f64 = last
+            .teams
+            .iter()
+            .chain(history.unplaced.iter())
+            .filter(|score| score.team == team.team)
+            .map(|score| score.total)
+            .sum();
+        assert!((total - team.total).abs() < 1e-9, "{}", team.team);
+    }
+}
+
+/// Event files in which zone packets are credited before the Hub's sends
+/// which carried them are seen
+fn event_files() -> Vec<Vec<Value>> {
+    vec![
+        vec![
+            recv("zone", "zonerecv1", "cosmos1alpha", "ibczone", "ibczone", 1),
+            recv("zone", "zonerecv2", "cosmos1beta", "ibczone", "ibczone", 2),
+            send("zone", "zonesend1", "ibczone", "ibczone", 1),
+        ],
+        vec![
+            recv("hub", "hubrecv1", "cosmos1beta", "ibczone", "ibczone", 1),
+            send("hub", "hubsend1", "ibczone", "ibczone", 1),
+            send("hub", "hubsend2", "ibczone", "ibczone", 2),
+        ],
+        vec![recv(
+            "zone",
+            "zonerecv3",
+            "cosmos1beta",
+            "ibczone",
+            "ibczone",
+            3,
+        )],
+    ]
+}
+
+/// Scoring each file into a shard and merging the shards in order, as
+/// `start --jobs` does, writes the same results and ledger as scoring the
+/// files one after the other
+#[test]
+fn merged_shards_score_like_sequential_files() {
+    let files = event_files();
+    let dir = scratch_dir("shards");
+
+    let mut sequential = score(&[]);
+    let mut ledger = Ledger::open(&dir.join("sequential.jsonl")).unwrap();
+
+    for file in files.iter() {
+        score_into(sequential.as_mut(), file);
+        sequential.flush_ledger(&mut ledger).unwrap();
+    }
+
+    let mut merged = score(&[]);
+    let mut ledger = Ledger::open(&dir.join("merged.jsonl")).unwrap();
+
+    let shards: Vec<_> = files
+        .iter()
+        .map(|file| {
+            let mut shard = merged.fork();
+            score_into(shard.as_mut(), file);
+            shard
+        })
+        .collect();
+
+    for shard in shards {
+        merged.merge(shard);
+        merged.flush_ledger(&mut ledger).unwrap();
+    }
+
+    assert_eq!(count(&sequential.report(), "beta", "packets_from_hub"), 2);
+    assert_eq!(render(merged.as_ref()), render(sequential.as_ref()));
+
+    let sequential = fs::read_to_string(dir.join("sequential.jsonl")).unwrap();
+    assert_eq!(sequential.lines().count(), 6);
+    assert_eq!(
+        fs::read_to_string(dir.join("merged.jsonl")).unwrap(),
+        sequential
+    );
+}
@@ -0,0 +1,157 @@
+//! Fixtures shared by the integration tests: the teams' configuration and
+//! envelopes of the IBC transactions they relay, as the RPC collector builds
+//! them.
+
+// Each test crate only uses some of the fixtures
+#![allow(dead_code)]
+
+use goz_scoring::config::GozScoringConfig;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::{env, fs, process};
+
+const CONFIG: &str = r#"
+hub_id = ["hub"]
+
+[scoring]
+hub_opaque_packets = 1.0
+packets_from_hub = 0.5
+opaque_packets_tx = 0.1
+round_trips = 0.5
+
+[[teams]]
+name = "alpha"
+address = "cosmos1alpha"
+client_id = "alphaclient"
+
+[[teams]]
+name = "beta"
+address = "cosmos1beta"
+client_id = "betaclient"
+"#;
+
+/// Configuration scoring the fixtures' teams, with the Hub at `hub`
+pub fn config() -> GozScoringConfig {
+    toml::from_str(CONFIG).expect("valid config")
+}
+
+/// Envelope carrying a transaction's flattened events, as the RPC collector
+/// builds them
+pub fn envelope(chain_id: &str, kind: &str, data: Value) -> Value {
+    json!({
+        "node": "http://localhost:26657",
+        "network": chain_id,
+        "msg": [{ "EventIBC": { kind: { "data": data } } }],
+    })
+}
+
+/// Transaction on `chain_id` sending a packet from `channel` to `dst_channel`
+pub fn send(chain_id: &str, hash: &str, channel: &str, dst_channel: &str, sequence: u64) -> Value {
+    envelope(
+        chain_id,
+        "PacketTransfer",
+        json!({
+            "tx.hash": [hash],
+            "tx.height": ["10"],
+            "message.action": ["transfer"],
+            "message.sender": ["cosmos1user"],
+            "send_packet.packet_src_port": ["transfer"],
+            "send_packet.packet_src_channel": [channel],
+            "send_packet.packet_dst_port": ["transfer"],
+            "send_packet.packet_dst_channel": [dst_channel],
+            "send_packet.packet_sequence": [sequence.to_string()],
+        }),
+    )
+}
+
+/// Transaction on `chain_id` in which `relayer` delivers a packet sent from
+/// `src_channel` on `channel`
+pub fn recv(
+    chain_id: &str,
+    hash: &str,
+    relayer: &str,
+    src_channel: &str,
+    channel: &str,
+    sequence: u64,
+) -> Value {
+    envelope(
+        chain_id,
+        "OpaquePacket",
+        json!({
+            "tx.hash": [hash],
+            "tx.height": ["20"],
+            "message.action": ["update_client", "ics04/opaque"],
+            "message.sender": [relayer, relayer],
+            "recv_packet.packet_src_port": ["transfer"],
+            "recv_packet.packet_src_channel": [src_channel],
+            "recv_packet.packet_dst_port": ["transfer"],
+            "recv_packet.packet_dst_channel": [channel],
+            "recv_packet.packet_sequence": [sequence.to_string()],
+        }),
+    )
+}
+
+/// Transaction on `chain_id` in which `relayer` delivers the acknowledgement
+/// of a packet it sent from `channel`
+pub fn ack(
+    chain_id: &str,
+    hash: &str,
+    relayer: &str,
+    channel: &str,
+    dst_channel: &str,
+    sequence: u64,
+) -> Value {
+    envelope(
+        chain_id,
+        "PacketTransfer",
+        json!({
+            "tx.hash": [hash],
+            "tx.height": ["30"],
+            "message.action": ["update_client", "acknowledge_packet"],
+            "message.sender": [relayer, relayer],
+            "acknowledge_packet.packet_src_port": ["transfer"],
+            "acknowledge_packet.packet_src_channel": [channel],
+            "acknowledge_packet.packet_dst_port": ["transfer"],
+            "acknowledge_packet.packet_dst_channel": [dst_channel],
+            "acknowledge_packet.packet_sequence": [sequence.to_string()],
+        }),
+    )
+}
+
+/// Handshake on `chain_id` opening `connection` on top of `client`
+pub fn connection_open(chain_id: &str, connection: &str, client: &str) -> Value {
+    json!({
+        "node": "http://localhost:26657",
+        "network": chain_id,
+        "msg": [{ "EventIBC": { "OpenInitConnection": {
+            "height": "5",
+            "connection_id": connection,
+            "client_id": client,
+            "counterparty_client_id": "counterpartyclient",
+        } } }],
+    })
+}
+
+/// Handshake on `chain_id` opening `channel` on top of `connection`
+pub fn channel_open(chain_id: &str, channel: &str, connection: &str) -> Value {
+    json!({
+        "node": "http://localhost:26657",
+        "network": chain_id,
+        "msg": [{ "EventIBC": { "OpenInitChannel": {
+            "height": "6",
+            "port_id": "transfer",
+            "channel_id": channel,
+            "connection_id": connection,
+            "counterparty_port_id": "transfer",
+            "counterparty_channel_id": "counterpartychannel",
+        } } }],
+    })
+}
+
+/// Empty directory for the files written by a test
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("goz_scoring_{}_{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("temporary directory is writable");
+    dir
+}